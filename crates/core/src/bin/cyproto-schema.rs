@@ -0,0 +1,6 @@
+//! Print the schema of the protocol as JSON, see the `schema` module of cyproto-core
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", cyproto_core::schema::to_json()?);
+    Ok(())
+}
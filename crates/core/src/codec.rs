@@ -0,0 +1,299 @@
+//! Turning frames into bytes for the wire and back
+//!
+//! A frame is the postcard encoding of a [`Frame`] followed by a little endian CRC-16 of
+//! those bytes, all of it COBS encoded and terminated by a zero byte.
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Frame, BYTES_MAX};
+
+/// The size of the checksum at the end of every frame
+pub const CRC_SIZE: usize = 2;
+
+/// The most bytes a frame takes on the wire when its postcard encoding is `len` bytes long
+///
+/// This counts the checksum, the worst case COBS overhead and the terminating zero byte.
+pub const fn wire_size(len: usize) -> usize {
+    cobs::max_encoding_length(len + CRC_SIZE) + 1
+}
+
+/// The longest the postcard encoding of a [`Frame`] can be to still fit in [`BYTES_MAX`] on the wire
+pub const FRAME_MAX: usize = {
+    let mut len = BYTES_MAX;
+    while wire_size(len) > BYTES_MAX {
+        len -= 1;
+    }
+    len
+};
+
+/// The reason a frame could not be encoded or decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodecError {
+    /// The frame does not fit in the buffer
+    BufferFull,
+    /// The COBS encoding of the frame is broken or too short to hold a checksum
+    Framing,
+    /// The checksum does not match the contents, a byte was corrupted on the way
+    Checksum,
+    /// The contents are not a valid message
+    Decode,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CodecError::BufferFull => "The frame does not fit in the buffer",
+            CodecError::Framing => "The frame is not properly encoded",
+            CodecError::Checksum => "The frame was corrupted on the way",
+            CodecError::Decode => "The frame does not hold a valid message",
+        })
+    }
+}
+
+impl core::error::Error for CodecError {}
+
+/// The CRC-16/CCITT-FALSE lookup table
+const CRC_TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Compute the CRC-16/CCITT-FALSE checksum of some bytes
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[usize::from((crc >> 8) as u8 ^ byte)]
+    })
+}
+
+/// Encode a frame into the buffer including the terminating zero byte
+///
+/// Returns the number of bytes written.
+pub fn encode<T: Serialize>(frame: &Frame<T>, buf: &mut [u8]) -> Result<usize, CodecError> {
+    let mut raw = [0; BYTES_MAX];
+    let len = postcard::to_slice(frame, &mut raw[..BYTES_MAX - CRC_SIZE])
+        .map_err(|_| CodecError::BufferFull)?
+        .len();
+    let crc = crc16(&raw[..len]);
+    raw[len..len + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+    let len = cobs::try_encode(&raw[..len + CRC_SIZE], buf).map_err(|_| CodecError::BufferFull)?;
+    *buf.get_mut(len).ok_or(CodecError::BufferFull)? = 0;
+    Ok(len + 1)
+}
+
+/// Undo the COBS encoding of a frame in place and check its checksum
+///
+/// The buffer holds a single frame and may include the terminating zero byte.
+/// Returns the postcard encoding of the [`Frame`].
+pub fn open(buf: &mut [u8]) -> Result<&[u8], CodecError> {
+    let len = cobs::decode_in_place(buf).map_err(|_| CodecError::Framing)?;
+    let len = len.checked_sub(CRC_SIZE).ok_or(CodecError::Framing)?;
+    let (data, crc) = buf[..len + CRC_SIZE].split_at(len);
+    if crc16(data).to_le_bytes() != crc {
+        return Err(CodecError::Checksum);
+    }
+    Ok(data)
+}
+
+/// Decode a frame in place, see [`open`]
+pub fn decode<'a, T: Deserialize<'a>>(buf: &'a mut [u8]) -> Result<Frame<T>, CodecError> {
+    postcard::from_bytes(open(buf)?).map_err(|_| CodecError::Decode)
+}
+
+/// Collects bytes from the wire until a whole frame has arrived
+///
+/// Bytes can be fed in chunks of any size. A frame longer than [`BYTES_MAX`] is dropped up to
+/// the next zero byte, and garbage only spoils the frame it ends up in, so the accumulator
+/// always falls back in step with the sender.
+#[derive(Debug)]
+pub struct FrameAccumulator {
+    buf: [u8; BYTES_MAX],
+    len: usize,
+    /// The current frame is too long and is being skipped up to its end
+    overflowed: bool,
+}
+
+/// The outcome of feeding bytes to a [`FrameAccumulator`]
+#[derive(Debug)]
+pub enum FeedResult<'a, 'b> {
+    /// Every byte was used up without completing a frame
+    Consumed,
+    /// A frame was completed, it is still encoded and can be decoded in place with [`decode`]
+    ///
+    /// `remaining` are the bytes after the frame that still need to be fed.
+    Frame { frame: &'b mut [u8], remaining: &'a [u8] },
+    /// A frame was longer than [`BYTES_MAX`] and was dropped
+    Overflow { remaining: &'a [u8] },
+}
+
+impl Default for FrameAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameAccumulator {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; BYTES_MAX],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Add bytes from the wire stopping at the end of the first frame in them
+    pub fn feed<'a, 'b>(&'b mut self, mut input: &'a [u8]) -> FeedResult<'a, 'b> {
+        loop {
+            let Some(end) = input.iter().position(|&byte| byte == 0) else {
+                self.extend(input);
+                return FeedResult::Consumed;
+            };
+            self.extend(&input[..end]);
+            let remaining = &input[end + 1..];
+
+            let len = core::mem::take(&mut self.len);
+            if core::mem::take(&mut self.overflowed) {
+                return FeedResult::Overflow { remaining };
+            }
+            // zero bytes with nothing between them carry no frame
+            if len == 0 {
+                input = remaining;
+                continue;
+            }
+            return FeedResult::Frame {
+                frame: &mut self.buf[..len],
+                remaining,
+            };
+        }
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        if self.overflowed {
+            return;
+        }
+        match self.buf.get_mut(self.len..self.len + data.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(data);
+                self.len += data.len();
+            }
+            None => {
+                self.overflowed = true;
+                self.len = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Command;
+
+    fn encoded(seq: u16, command: Command) -> ([u8; BYTES_MAX], usize) {
+        let mut buf = [0; BYTES_MAX];
+        let len = encode(&Frame::new(seq, command), &mut buf).unwrap();
+        (buf, len)
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (mut buf, len) = encoded(7, Command::Drive { distance: 12.5, speed: 200 });
+        assert_eq!(buf[len - 1], 0);
+        let frame: Frame<Command> = decode(&mut buf[..len]).unwrap();
+        assert_eq!(frame.seq, 7);
+        assert!(matches!(frame.msg, Command::Drive { distance, speed: 200 } if distance == 12.5));
+    }
+
+    #[test]
+    fn flipped_byte_fails_checksum() {
+        let (mut buf, len) = encoded(7, Command::Ping { nonce: 0x0102_0304 });
+        buf[2] ^= 0x10;
+        assert_eq!(decode::<Command>(&mut buf[..len]).unwrap_err(), CodecError::Checksum);
+    }
+
+    #[test]
+    fn frame_shorter_than_checksum() {
+        let mut buf = [0x02, 0x01, 0x00];
+        assert_eq!(open(&mut buf).unwrap_err(), CodecError::Framing);
+    }
+
+    /// The sequence number of the frame that was completed and the bytes left after it
+    fn fed_frame<'a>(res: FeedResult<'a, '_>) -> (u16, &'a [u8]) {
+        match res {
+            FeedResult::Frame { frame, remaining } => (decode::<Command>(frame).unwrap().seq, remaining),
+            res => panic!("expected a frame, got {res:?}"),
+        }
+    }
+
+    #[test]
+    fn frame_split_across_feeds() {
+        let (buf, len) = encoded(3, Command::Turn { angle: -90., speed: 100 });
+        let mut acc = FrameAccumulator::new();
+        let (head, tail) = buf[..len].split_at(len - 2);
+        for chunk in head.chunks(3) {
+            assert!(matches!(acc.feed(chunk), FeedResult::Consumed));
+        }
+        assert_eq!(fed_frame(acc.feed(tail)), (3, &[][..]));
+    }
+
+    #[test]
+    fn two_frames_in_one_chunk() {
+        let (first, first_len) = encoded(1, Command::ReadSensors);
+        let (second, second_len) = encoded(2, Command::Stop);
+        let mut input = [0; 2 * BYTES_MAX];
+        input[..first_len].copy_from_slice(&first[..first_len]);
+        input[first_len..first_len + second_len].copy_from_slice(&second[..second_len]);
+
+        let mut acc = FrameAccumulator::new();
+        let (seq, remaining) = fed_frame(acc.feed(&input[..first_len + second_len]));
+        assert_eq!(seq, 1);
+        assert_eq!(remaining, &second[..second_len]);
+        assert_eq!(fed_frame(acc.feed(remaining)), (2, &[][..]));
+    }
+
+    #[test]
+    fn oversized_frame_overflows_then_resyncs() {
+        let (frame, len) = encoded(4, Command::ReadSensors);
+        let mut input = [0x11; 2 * BYTES_MAX];
+        input[BYTES_MAX + 10] = 0;
+        input[BYTES_MAX + 11..BYTES_MAX + 11 + len].copy_from_slice(&frame[..len]);
+        let input = &input[..BYTES_MAX + 11 + len];
+
+        let mut acc = FrameAccumulator::new();
+        let remaining = match acc.feed(input) {
+            FeedResult::Overflow { remaining } => remaining,
+            res => panic!("expected an overflow, got {res:?}"),
+        };
+        assert_eq!(remaining, &frame[..len]);
+        assert_eq!(fed_frame(acc.feed(remaining)), (4, &[][..]));
+    }
+
+    #[test]
+    fn repeated_zero_bytes_are_skipped() {
+        let (frame, len) = encoded(5, Command::ReadSensors);
+        let mut input = [0; BYTES_MAX];
+        input[3..3 + len].copy_from_slice(&frame[..len]);
+
+        let mut acc = FrameAccumulator::new();
+        assert_eq!(fed_frame(acc.feed(&input[..3 + len])), (5, &[][..]));
+        assert!(matches!(acc.feed(&[0, 0]), FeedResult::Consumed));
+    }
+}
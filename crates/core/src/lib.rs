@@ -1,32 +1,452 @@
 #![no_std]
+use postcard::experimental::max_size::MaxSize;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod codec;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod transport;
+
+/// The version of the protocol spoken by this build of cyproto-core
+///
+/// This is bumped whenever the encoding of an existing message changes, both
+/// sides exchange it with [`Command::Hello`] and [`Response::Hello`] on connect.
+pub const PROTOCOL_VERSION: u16 = 7;
+
+/// The size of the buffers frames are encoded into and decoded from, no frame is ever longer
 pub const BYTES_MAX: usize = 256;
-pub const SCAN_MAX: usize = BYTES_MAX / core::mem::size_of::<ObjectData>();
+
+/// What a [`Response::ScanChunk`] or [`Response::SweepChunk`] frame holds besides its items,
+/// the sequence number, the variant, the index, the total and the number of items
+const CHUNK_OVERHEAD: usize = Seq::POSTCARD_MAX_SIZE + 1 + 2 * u16::POSTCARD_MAX_SIZE + 1;
+
+/// The maximum number of objects in a single scan frame
+pub const SCAN_MAX: usize = (codec::FRAME_MAX - CHUNK_OVERHEAD) / ObjectData::POSTCARD_MAX_SIZE;
+/// The maximum number of readings in a single sweep frame
+pub const SWEEP_MAX: usize = (codec::FRAME_MAX - CHUNK_OVERHEAD) / SweepData::POSTCARD_MAX_SIZE;
+
+/// The most bytes a [`Command`] frame can take on the wire
+pub const COMMAND_MAX_SIZE: usize = codec::wire_size(Frame::<Command>::POSTCARD_MAX_SIZE);
+/// The most bytes a [`Response`] frame can take on the wire
+pub const RESPONSE_MAX_SIZE: usize = codec::wire_size(Frame::<Response>::POSTCARD_MAX_SIZE);
+
+const _: () = assert!(COMMAND_MAX_SIZE <= BYTES_MAX, "a command can be longer than BYTES_MAX");
+const _: () = assert!(RESPONSE_MAX_SIZE <= BYTES_MAX, "a response can be longer than BYTES_MAX");
 
 pub type Bytes = heapless::Vec<u8, SCAN_MAX>;
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+/// The sequence number carried by every frame
+///
+/// The instructor picks a new sequence number for each command it sends and
+/// the executor echoes it back in the matching response.
+pub type Seq = u16;
+
+/// The sequence number used for frames that do not answer any known command
+///
+/// The instructor never sends a command with this sequence number.
+pub const NO_SEQ: Seq = 0;
+
+/// The maximum length of the detail message in [`Response::Error`]
+pub const DETAIL_MAX: usize = 32;
+
+/// The maximum length of the message in [`Response::Log`]
+pub const LOG_MAX: usize = 96;
+
+/// How long the executor keeps following a [`Command::SetVelocity`] without hearing a new one
+///
+/// The instructor must resend the velocity well within this time for as long as the robot
+/// should keep moving, once it runs out the executor stops the robot on its own.
+pub const VELOCITY_TIMEOUT_MS: u32 = 500;
+
+/// A single message on the wire along with its sequence number
+#[derive(Debug, Deserialize, Serialize, MaxSize)]
+pub struct Frame<T> {
+    pub seq: Seq,
+    pub msg: T,
+}
+
+impl<T> Frame<T> {
+    pub const fn new(seq: Seq, msg: T) -> Self {
+        Self { seq, msg }
+    }
+}
+
+/// The set of optional commands an executor supports
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, MaxSize)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// The executor can handle [`Command::Scan`]
+    pub const SCAN: Self = Self(1 << 0);
+    /// The executor can interrupt a running command with [`Command::Stop`]
+    pub const STOP: Self = Self(1 << 1);
+    /// The executor can handle [`Command::Sweep`]
+    pub const SWEEP: Self = Self(1 << 2);
+    /// The executor sends [`Response::Telemetry`] on its own
+    pub const TELEMETRY: Self = Self(1 << 3);
+    /// The executor can handle [`Command::Arc`]
+    pub const ARC: Self = Self(1 << 4);
+    /// The executor can handle [`Command::SetVelocity`]
+    pub const TELEOP: Self = Self(1 << 5);
+    /// The executor answers [`Command::Ping`] even while running another command
+    pub const PING: Self = Self(1 << 6);
+    /// The executor can handle [`Command::ReadSensors`]
+    pub const SENSORS: Self = Self(1 << 7);
+    /// The executor sends [`Response::Boundary`] on its own whenever it crosses the field's edge
+    pub const BOUNDARY: Self = Self(1 << 8);
+    /// The executor can handle [`Command::Sequence`]
+    pub const SEQUENCE: Self = Self(1 << 9);
+    /// The executor can handle [`Command::GetConfig`] and [`Command::SetConfig`]
+    pub const CONFIG: Self = Self(1 << 10);
+    /// The executor sends [`Response::Log`] messages
+    pub const LOG: Self = Self(1 << 11);
+    /// The executor answers commands with [`Response::Ack`] or [`Response::Nack`] as soon as
+    /// it has parsed them, see [`Command::acknowledged`]
+    pub const ACK: Self = Self(1 << 12);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// The reason the executor could not carry out a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, MaxSize)]
+pub enum ErrorCode {
+    /// The command could not be decoded
+    Decode,
+    /// The command is not supported by the executor
+    Unsupported,
+    /// An argument of the command is out of range
+    InvalidArgument,
+    /// The hardware failed while running the command
+    Hardware,
+    /// Any other failure, see the detail message
+    Other,
+    /// The executor is still running another command
+    Busy,
+}
+
+/// How important a [`Response::Log`] message is, from most to least important
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, MaxSize)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+/// One of the four cliff sensors under the front of the robot
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, MaxSize)]
+pub enum CliffSensor {
+    FrontLeft,
+    Left,
+    Right,
+    FrontRight,
+}
+
+/// Why a drive ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, MaxSize)]
+pub enum DriveTermination {
+    /// The full distance was driven
+    Completed,
+    BumpLeft,
+    BumpRight,
+    BumpBoth,
+    CliffFrontLeft,
+    CliffLeft,
+    CliffRight,
+    CliffFrontRight,
+    /// One of the cliff sensors saw the white tape at the edge of the field
+    BoundaryFrontLeft,
+    BoundaryLeft,
+    BoundaryRight,
+    BoundaryFrontRight,
+    /// The drive was interrupted by [`Command::Stop`]
+    StoppedByHost,
+    /// The drive took too long to finish
+    Timeout,
+}
+
+impl DriveTermination {
+    pub const fn is_bump(self) -> bool {
+        matches!(self, Self::BumpLeft | Self::BumpRight | Self::BumpBoth)
+    }
+
+    pub const fn is_cliff(self) -> bool {
+        self.cliff().is_some()
+    }
+
+    /// The sensor that saw a cliff if that is what ended the drive
+    pub const fn cliff(self) -> Option<CliffSensor> {
+        match self {
+            Self::CliffFrontLeft => Some(CliffSensor::FrontLeft),
+            Self::CliffLeft => Some(CliffSensor::Left),
+            Self::CliffRight => Some(CliffSensor::Right),
+            Self::CliffFrontRight => Some(CliffSensor::FrontRight),
+            _ => None,
+        }
+    }
+
+    /// The sensor that saw the edge of the field if that is what ended the drive
+    pub const fn boundary(self) -> Option<CliffSensor> {
+        match self {
+            Self::BoundaryFrontLeft => Some(CliffSensor::FrontLeft),
+            Self::BoundaryLeft => Some(CliffSensor::Left),
+            Self::BoundaryRight => Some(CliffSensor::Right),
+            Self::BoundaryFrontRight => Some(CliffSensor::FrontRight),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, MaxSize)]
 pub struct ObjectData {
     pub distance: f32,
     pub angle: u8,
     pub width: f32,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// The distance sensors used to find objects during a scan
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, MaxSize)]
+pub enum ScanSensor {
+    Ir,
+    Ping,
+    #[default]
+    Both,
+}
+
+/// The raw sensor readings taken at a single angle of a sweep
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, MaxSize)]
+pub struct SweepData {
+    pub angle: u8,
+    pub ir_distance: f32,
+    pub ping_distance: f32,
+}
+
+/// Where the robot thinks it is relative to where it started
+///
+/// The robot starts at the origin facing along the positive y axis, `x` and `y` are in
+/// centimeters and `heading` is in degrees counter-clockwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, MaxSize)]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+}
+
+/// The state of the robot's hardware, sent periodically without being asked for
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, MaxSize)]
+pub struct Telemetry {
+    pub battery_voltage: f32,
+    pub left_encoder: i32,
+    pub right_encoder: i32,
+    /// The raw readings of the front left, left, right and front right cliff sensors
+    pub cliff: [u16; 4],
+    pub pose: Pose,
+}
+
+/// A one-off reading of every sensor on the robot
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, MaxSize)]
+pub struct Sensors {
+    pub bump_left: bool,
+    pub bump_right: bool,
+    /// The raw readings of the front left, left, right and front right cliff sensors
+    pub cliff: [u16; 4],
+    /// The angle of the servo the distance sensors are mounted on
+    pub servo_angle: u8,
+    pub ir_distance: f32,
+    pub ping_distance: f32,
+    pub battery_voltage: f32,
+}
+
+/// The maximum length of a configuration key
+pub const CONFIG_KEY_MAX: usize = 16;
+
+/// The name of a calibration value stored on the robot such as `wheel_factor`
+pub type ConfigKey = heapless::String<CONFIG_KEY_MAX>;
+
+/// The maximum number of steps in a [`Command::Sequence`]
+pub const SEQUENCE_MAX: usize = 8;
+
+/// A single command run as part of a [`Command::Sequence`]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, MaxSize)]
+pub enum Step {
+    Drive { distance: f32, speed: u16 },
+    Turn { angle: f32, speed: u16 },
+    Arc { radius: f32, distance: f32, speed: u16 },
+    Scan {
+        start: u8,
+        end: u8,
+        step: Option<u8>,
+        sensor: ScanSensor,
+    },
+}
+
+/// The steps of a [`Command::Sequence`]
+pub type Steps = heapless::Vec<Step, SEQUENCE_MAX>;
+
+/// Commands sent from the instructor to the executor
+///
+/// `Hello` must remain the first variant so that the handshake can be decoded
+/// by every version of the protocol.
+#[derive(Debug, Deserialize, Serialize, MaxSize)]
 pub enum Command {
+    Hello { version: u16 },
     Drive { distance: f32, speed: u16 },
     Turn { angle: f32, speed: u16 },
-    Scan { start: u8, end: u8 },
+    /// Scan for objects from `start` to `end`, moving the servo `step` degrees at a time
+    /// or by the executor's default step if it is not given
+    Scan {
+        start: u8,
+        end: u8,
+        step: Option<u8>,
+        sensor: ScanSensor,
+    },
+    /// Stop whatever the robot is currently doing
+    ///
+    /// If a drive, turn or scan is in progress it is cut short and its result is sent
+    /// back as the answer to the stop, otherwise the executor answers with
+    /// [`Response::Stopped`].
+    Stop,
+    /// Sweep the sensors from `start` to `end` in steps of `step` degrees reporting the
+    /// raw readings at every angle
+    Sweep { start: u8, end: u8, step: u8 },
+    /// Drive `distance` centimeters along a circle with the given `radius`
+    ///
+    /// A positive radius curves counter-clockwise (left) and a negative radius curves
+    /// clockwise (right), a negative distance drives backwards along the same circle.
+    Arc { radius: f32, distance: f32, speed: u16 },
+    /// Keep moving at `linear` centimeters per second while turning counter-clockwise at
+    /// `angular` degrees per second
+    ///
+    /// The robot follows the velocity until the next one arrives or until
+    /// [`VELOCITY_TIMEOUT_MS`] passes without one, a zero velocity stops it. There is no
+    /// response so the velocity can double as a heartbeat.
+    SetVelocity { linear: f32, angular: f32 },
+    /// Check that the link is alive, the executor answers right away with a
+    /// [`Response::Pong`] carrying the same `nonce` even while running another command
+    Ping { nonce: u32 },
+    /// Read every sensor once without moving
+    ReadSensors,
+    /// Run the steps back to back
+    ///
+    /// Every step is answered with its usual result followed by a [`Response::SequenceDone`]
+    /// once the sequence is over. A drive, turn or arc that does not get as far as it was asked
+    /// ends the sequence early, a rejected step is answered with [`Response::Error`] instead of
+    /// `SequenceDone` and a stop is answered with the result of the interrupted step like any
+    /// other command.
+    Sequence(Steps),
+    /// Read a calibration value, answered with [`Response::Config`]
+    GetConfig { key: ConfigKey },
+    /// Change a calibration value until the robot restarts, answered with [`Response::Config`]
+    /// holding the value the robot ended up using
+    SetConfig { key: ConfigKey, value: f32 },
+}
+
+impl Command {
+    /// Whether the executor acknowledges this command when it advertises [`Capabilities::ACK`]
+    ///
+    /// The handshake happens before the capabilities are known, and pings and velocities are
+    /// answered or replaced too quickly for an acknowledgement to be of any use.
+    pub fn acknowledged(&self) -> bool {
+        !matches!(
+            self,
+            Command::Hello { .. } | Command::Ping { .. } | Command::SetVelocity { .. }
+        )
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Responses sent from the executor back to the instructor
+///
+/// `Hello` must remain the first variant so that the handshake can be decoded
+/// by every version of the protocol.
+#[derive(Debug, Deserialize, Serialize, MaxSize)]
+#[allow(clippy::large_enum_variant)]
 pub enum Response {
+    Hello {
+        version: u16,
+        capabilities: Capabilities,
+        bytes_max: u16,
+        scan_max: u16,
+    },
+    /// The result of a drive, `pose` is where the robot's own odometry puts it afterwards
+    /// if it keeps track of that
     DriveDone {
         total_distance: f32,
-        bump_detected: bool,
-        cliff_detected: bool,
+        termination: DriveTermination,
+        pose: Option<Pose>,
+    },
+    /// The result of a turn, `pose` works the same as in [`Response::DriveDone`]
+    TurnDone { total_angle: f32, pose: Option<Pose> },
+    ScanDone { data: heapless::Vec<ObjectData, SCAN_MAX> },
+    Error {
+        code: ErrorCode,
+        detail: heapless::String<DETAIL_MAX>,
+    },
+    /// The answer to a stop when nothing was running
+    Stopped,
+    /// One piece of a scan result too large for a single [`Response::ScanDone`]
+    ///
+    /// `index` counts up from 0 and `total` is the number of chunks in the scan, or 0
+    /// if the executor does not know it up front. The chunks are always followed by a
+    /// [`Response::ScanEnd`].
+    ScanChunk {
+        index: u16,
+        total: u16,
+        data: heapless::Vec<ObjectData, SCAN_MAX>,
+    },
+    /// The end of a chunked scan along with the number of objects that were sent
+    ScanEnd { count: u16 },
+    /// One piece of the readings of a sweep, these work the same as [`Response::ScanChunk`]
+    SweepChunk {
+        index: u16,
+        total: u16,
+        data: heapless::Vec<SweepData, SWEEP_MAX>,
+    },
+    /// The end of a sweep along with the number of readings that were sent
+    SweepEnd { count: u16 },
+    /// Periodic state of the robot, this does not answer any command and is sent with [`NO_SEQ`]
+    Telemetry(Telemetry),
+    /// How far a drive has gotten so far, sent any number of times before [`Response::DriveDone`]
+    DriveProgress { distance_so_far: f32 },
+    /// How far a turn has gotten so far, sent any number of times before [`Response::TurnDone`]
+    TurnProgress { angle_so_far: f32 },
+    /// The distance driven along the arc and the angle the robot turned while doing so,
+    /// `pose` works the same as in [`Response::DriveDone`]
+    ArcDone {
+        total_distance: f32,
+        total_angle: f32,
+        pose: Option<Pose>,
+    },
+    /// The answer to a [`Command::Ping`]
+    Pong { nonce: u32 },
+    /// The answer to a [`Command::ReadSensors`]
+    Sensors(Sensors),
+    /// A cliff sensor crossed the white tape at the edge of the field, this does not answer
+    /// any command and is sent with [`NO_SEQ`] whether or not the robot is running a command
+    ///
+    /// `signal` is the raw reading of the sensor that saw the tape.
+    Boundary { sensor: CliffSensor, signal: u16 },
+    /// The end of a [`Command::Sequence`] along with the number of steps that completed
+    SequenceDone { completed: u8 },
+    /// The current value of a calibration value
+    Config { key: ConfigKey, value: f32 },
+    /// A message from the firmware for whoever is watching, this does not answer any command
+    /// and is sent with [`NO_SEQ`]
+    Log {
+        level: LogLevel,
+        message: heapless::String<LOG_MAX>,
     },
-    TurnDone { total_angle: f32 },
-    ScanDone { data: heapless::Vec<ObjectData, SCAN_MAX> }
+    /// The command was received and is being carried out, its result follows later
+    Ack,
+    /// The command was received but will not be carried out, nothing else is sent for it
+    Nack { reason: ErrorCode },
 }
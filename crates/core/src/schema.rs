@@ -0,0 +1,176 @@
+//! A description of the messages for tools written in other languages
+//!
+//! The schema lists every variant of [`Command`] and [`Response`] keyed by its postcard
+//! discriminant, with the fields of every variant and struct in the order they are encoded.
+//! It carries the [`PROTOCOL_VERSION`] it was generated for, so a tool can compare it with the
+//! version the robot reports in [`Response::Hello`] and refuse to talk to a different protocol.
+//! The fingerprint changes with any change to the limits or the formats, so a tool generated from
+//! an older schema can tell that the messages changed even if the version was not bumped.
+//!
+//! Run `cargo run -p cyproto-core --features schema --bin cyproto-schema` to print it as JSON.
+
+use std::string::String;
+
+use serde::Serialize;
+use serde_reflection::{Registry, Tracer, TracerConfig};
+
+use crate::{
+    CliffSensor, Command, DriveTermination, ErrorCode, LogLevel, ObjectData, Response, ScanSensor, Step,
+    BYTES_MAX, COMMAND_MAX_SIZE, CONFIG_KEY_MAX, DETAIL_MAX, LOG_MAX, NO_SEQ, PROTOCOL_VERSION,
+    RESPONSE_MAX_SIZE, SCAN_MAX, SEQUENCE_MAX, SWEEP_MAX,
+};
+
+/// How the messages in [`Schema::formats`] end up on the wire
+const ENCODING: &str = "Every message is wrapped in a Frame holding the sequence number as a u16 \
+    followed by the message. The frame is encoded with postcard, followed by the little endian \
+    CRC-16/CCITT-FALSE of those bytes, COBS encoded and terminated by a zero byte. \
+    Postcard writes integers wider than a byte as LEB128 varints, zigzag encoded if signed, floats \
+    as little endian IEEE 754, enum variants as the varint of their index, options as a 0 or 1 \
+    byte before the value, and strings and sequences as the varint of their length before the \
+    items. Structs and arrays are their fields in order without any prefix.";
+
+/// The limits on the length of the strings and sequences in the messages
+#[derive(Debug, Serialize)]
+pub struct Limits {
+    pub bytes_max: usize,
+    pub command_max_size: usize,
+    pub response_max_size: usize,
+    pub scan_max: usize,
+    pub sweep_max: usize,
+    pub sequence_max: usize,
+    pub config_key_max: usize,
+    pub detail_max: usize,
+    pub log_max: usize,
+    pub no_seq: u16,
+}
+
+/// Everything needed to encode commands and decode responses without cyproto-core
+#[derive(Debug, Serialize)]
+pub struct Schema {
+    /// The version the executor reports in the handshake when it speaks this schema
+    pub protocol_version: u16,
+    /// A hash of [`Schema::limits`] and [`Schema::formats`] as 16 hex digits
+    pub fingerprint: String,
+    pub encoding: &'static str,
+    pub limits: Limits,
+    /// The serde data model of [`Command`], [`Response`], [`ObjectData`] and every type they use
+    pub formats: Registry,
+}
+
+/// Describe the messages of this build of cyproto-core
+pub fn schema() -> serde_reflection::Result<Schema> {
+    let mut tracer = Tracer::new(TracerConfig::default());
+    // every variant of an enum is only explored when the enum itself is traced
+    tracer.trace_simple_type::<ErrorCode>()?;
+    tracer.trace_simple_type::<LogLevel>()?;
+    tracer.trace_simple_type::<CliffSensor>()?;
+    tracer.trace_simple_type::<DriveTermination>()?;
+    tracer.trace_simple_type::<ScanSensor>()?;
+    tracer.trace_simple_type::<Step>()?;
+    tracer.trace_simple_type::<Command>()?;
+    tracer.trace_simple_type::<Response>()?;
+    tracer.trace_simple_type::<ObjectData>()?;
+
+    let limits = Limits {
+        bytes_max: BYTES_MAX,
+        command_max_size: COMMAND_MAX_SIZE,
+        response_max_size: RESPONSE_MAX_SIZE,
+        scan_max: SCAN_MAX,
+        sweep_max: SWEEP_MAX,
+        sequence_max: SEQUENCE_MAX,
+        config_key_max: CONFIG_KEY_MAX,
+        detail_max: DETAIL_MAX,
+        log_max: LOG_MAX,
+        no_seq: NO_SEQ,
+    };
+    let formats = tracer.registry()?;
+    // the registry is sorted by name so the same formats always give the same JSON
+    let described =
+        serde_json::to_vec(&(&limits, &formats)).expect("the schema is always valid JSON");
+
+    Ok(Schema {
+        protocol_version: PROTOCOL_VERSION,
+        fingerprint: std::format!("{:016x}", fnv1a(&described)),
+        encoding: ENCODING,
+        limits,
+        formats,
+    })
+}
+
+/// The 64 bit FNV-1a hash, stable across builds and platforms unlike the std hashers
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The schema as pretty printed JSON
+pub fn to_json() -> serde_reflection::Result<String> {
+    Ok(serde_json::to_string_pretty(&schema()?).expect("the schema is always valid JSON"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_reflection::{ContainerFormat, Format, FormatHolder};
+
+    use super::*;
+
+    fn variants(schema: &Schema, name: &str) -> std::vec::Vec<String> {
+        match &schema.formats[name] {
+            ContainerFormat::Enum(variants) => {
+                // the keys are the discriminants so they must count up from 0 without gaps
+                assert!(variants.keys().copied().eq(0..variants.len() as u32));
+                variants.values().map(|variant| variant.name.clone()).collect()
+            }
+            format => panic!("{name} is not an enum: {format:?}"),
+        }
+    }
+
+    #[test]
+    fn lists_every_command() {
+        let schema = schema().unwrap();
+        assert_eq!(
+            variants(&schema, "Command"),
+            [
+                "Hello", "Drive", "Turn", "Scan", "Stop", "Sweep", "Arc", "SetVelocity", "Ping",
+                "ReadSensors", "Sequence", "GetConfig", "SetConfig",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_every_response() {
+        let schema = schema().unwrap();
+        assert_eq!(
+            variants(&schema, "Response"),
+            [
+                "Hello", "DriveDone", "TurnDone", "ScanDone", "Error", "Stopped", "ScanChunk",
+                "ScanEnd", "SweepChunk", "SweepEnd", "Telemetry", "DriveProgress", "TurnProgress",
+                "ArcDone", "Pong", "Sensors", "Boundary", "SequenceDone", "Config", "Log", "Ack",
+                "Nack",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_type_is_described() {
+        let schema = schema().unwrap();
+        for format in schema.formats.values() {
+            let mut unknown = false;
+            format
+                .visit(&mut |format| {
+                    unknown |= matches!(format, Format::Variable(_));
+                    Ok(())
+                })
+                .unwrap();
+            assert!(!unknown, "incomplete format {format:?}");
+        }
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        assert_eq!(schema().unwrap().fingerprint, schema().unwrap().fingerprint);
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
@@ -0,0 +1,309 @@
+//! Sending and receiving frames over any stream of bytes
+//!
+//! A [`Link`] does the framing on top of a [`Transport`], so the same code can talk to the
+//! robot over TCP, a serial port, an in-memory pipe or a recording.
+
+use core::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    codec::{self, CodecError, FeedResult, FrameAccumulator},
+    Command, Frame, Response, Seq, BYTES_MAX,
+};
+
+/// A stream of bytes to the other side
+///
+/// This mirrors the blocking `Read` and `Write` traits of embedded-io, anything implementing
+/// those can be used through [`EmbeddedIo`] with the `embedded-io` feature and anything
+/// implementing the std ones through [`StdIo`] with the `std` feature.
+pub trait Transport {
+    type Error;
+
+    /// Read at least one byte into `buf` returning how many were read,
+    /// `Ok(0)` means the other side is gone
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Write every byte of `data`
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// The async version of [`Transport`] enabled with the `async` feature
+#[cfg(feature = "async")]
+pub trait AsyncTransport {
+    type Error;
+
+    /// Read at least one byte into `buf` returning how many were read,
+    /// `Ok(0)` means the other side is gone
+    fn read(&mut self, buf: &mut [u8]) -> impl core::future::Future<Output = Result<usize, Self::Error>>;
+
+    /// Write every byte of `data`
+    fn write_all(&mut self, data: &[u8]) -> impl core::future::Future<Output = Result<(), Self::Error>>;
+}
+
+/// The reason a frame could not be sent or received
+#[derive(Debug)]
+pub enum LinkError<E> {
+    /// The transport failed
+    Transport(E),
+    /// The other side is gone
+    Closed,
+    /// A frame could not be encoded or a received frame was dropped, the link is still usable
+    Codec(CodecError),
+}
+
+impl<E: fmt::Display> fmt::Display for LinkError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::Transport(err) => err.fmt(f),
+            LinkError::Closed => f.write_str("The other side closed the connection"),
+            LinkError::Codec(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: core::error::Error> core::error::Error for LinkError<E> {}
+
+/// Frames sent and received over a [`Transport`]
+///
+/// Received bytes are kept between calls, so a transport that times out or would block
+/// can be read again later without losing a partial frame.
+#[derive(Debug)]
+pub struct Link<T> {
+    transport: T,
+    frames: FrameAccumulator,
+    /// Bytes read from the transport, the ones from `start` to `end` were not fed yet
+    input: [u8; BYTES_MAX],
+    start: usize,
+    end: usize,
+}
+
+impl<T> Link<T> {
+    pub const fn new(transport: T) -> Self {
+        Self {
+            transport,
+            frames: FrameAccumulator::new(),
+            input: [0; BYTES_MAX],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Decode the next frame out of the bytes that were already read, if a whole one is there
+    fn buffered<M: DeserializeOwned>(&mut self) -> Option<Result<Frame<M>, CodecError>> {
+        while self.start < self.end {
+            match self.frames.feed(&self.input[self.start..self.end]) {
+                FeedResult::Consumed => self.start = self.end,
+                FeedResult::Frame { frame, remaining } => {
+                    self.start = self.end - remaining.len();
+                    return Some(codec::decode(frame));
+                }
+                FeedResult::Overflow { remaining } => {
+                    self.start = self.end - remaining.len();
+                    return Some(Err(CodecError::BufferFull));
+                }
+            }
+        }
+        None
+    }
+
+    /// Record how many fresh bytes were read into the input buffer
+    fn filled<E>(&mut self, len: usize) -> Result<(), LinkError<E>> {
+        if len == 0 {
+            return Err(LinkError::Closed);
+        }
+        self.start = 0;
+        self.end = len;
+        Ok(())
+    }
+}
+
+impl<T: Transport> Link<T> {
+    /// Send a single frame
+    pub fn send<M: Serialize>(&mut self, frame: &Frame<M>) -> Result<(), LinkError<T::Error>> {
+        let mut buf = [0; BYTES_MAX];
+        let len = codec::encode(frame, &mut buf).map_err(LinkError::Codec)?;
+        self.transport.write_all(&buf[..len]).map_err(LinkError::Transport)
+    }
+
+    /// Wait for the next frame
+    pub fn recv<M: DeserializeOwned>(&mut self) -> Result<Frame<M>, LinkError<T::Error>> {
+        loop {
+            if let Some(res) = self.buffered() {
+                return res.map_err(LinkError::Codec);
+            }
+            let len = self.transport.read(&mut self.input).map_err(LinkError::Transport)?;
+            self.filled(len)?;
+        }
+    }
+
+    pub fn send_command(&mut self, seq: Seq, command: &Command) -> Result<(), LinkError<T::Error>> {
+        self.send(&Frame::new(seq, command))
+    }
+
+    pub fn recv_command(&mut self) -> Result<Frame<Command>, LinkError<T::Error>> {
+        self.recv()
+    }
+
+    pub fn send_response(&mut self, seq: Seq, response: &Response) -> Result<(), LinkError<T::Error>> {
+        self.send(&Frame::new(seq, response))
+    }
+
+    pub fn recv_response(&mut self) -> Result<Frame<Response>, LinkError<T::Error>> {
+        self.recv()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncTransport> Link<T> {
+    /// Send a single frame, see [`Link::send`]
+    pub async fn send_async<M: Serialize>(&mut self, frame: &Frame<M>) -> Result<(), LinkError<T::Error>> {
+        let mut buf = [0; BYTES_MAX];
+        let len = codec::encode(frame, &mut buf).map_err(LinkError::Codec)?;
+        self.transport.write_all(&buf[..len]).await.map_err(LinkError::Transport)
+    }
+
+    /// Wait for the next frame, see [`Link::recv`]
+    pub async fn recv_async<M: DeserializeOwned>(&mut self) -> Result<Frame<M>, LinkError<T::Error>> {
+        loop {
+            if let Some(res) = self.buffered() {
+                return res.map_err(LinkError::Codec);
+            }
+            let len = self.transport.read(&mut self.input).await.map_err(LinkError::Transport)?;
+            self.filled(len)?;
+        }
+    }
+
+    pub async fn send_command_async(&mut self, seq: Seq, command: &Command) -> Result<(), LinkError<T::Error>> {
+        self.send_async(&Frame::new(seq, command)).await
+    }
+
+    pub async fn recv_response_async(&mut self) -> Result<Frame<Response>, LinkError<T::Error>> {
+        self.recv_async().await
+    }
+}
+
+/// A [`Transport`] over anything implementing the std `Read` and `Write` traits
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StdIo<S>(pub S);
+
+#[cfg(feature = "std")]
+impl<S: std::io::Read + std::io::Write> Transport for StdIo<S> {
+    type Error = std::io::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buf)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(data)
+    }
+}
+
+/// A [`Transport`] over anything implementing the embedded-io `Read` and `Write` traits
+#[cfg(feature = "embedded-io")]
+#[derive(Debug)]
+pub struct EmbeddedIo<S>(pub S);
+
+#[cfg(feature = "embedded-io")]
+impl<S: embedded_io::Read + embedded_io::Write> Transport for EmbeddedIo<S> {
+    type Error = S::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buf)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An in-memory transport where everything written can be read back, a few bytes at a time
+    struct Pipe {
+        data: [u8; 4 * BYTES_MAX],
+        read: usize,
+        written: usize,
+    }
+
+    /// The most bytes a single read of a [`Pipe`] returns
+    const PIPE_CHUNK: usize = 5;
+
+    impl Pipe {
+        fn new() -> Self {
+            Self {
+                data: [0; 4 * BYTES_MAX],
+                read: 0,
+                written: 0,
+            }
+        }
+    }
+
+    impl Transport for Pipe {
+        type Error = ();
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(PIPE_CHUNK).min(self.written - self.read);
+            buf[..len].copy_from_slice(&self.data[self.read..self.read + len]);
+            self.read += len;
+            Ok(len)
+        }
+
+        fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let dst = self.data.get_mut(self.written..self.written + data.len()).ok_or(())?;
+            dst.copy_from_slice(data);
+            self.written += data.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn command_round_trip() {
+        let mut link = Link::new(Pipe::new());
+        link.send_command(9, &Command::Arc { radius: 30., distance: 50., speed: 150 }).unwrap();
+
+        let frame = link.recv_command().unwrap();
+        assert_eq!(frame.seq, 9);
+        assert!(matches!(frame.msg, Command::Arc { speed: 150, .. }));
+        assert!(matches!(link.recv_command(), Err(LinkError::Closed)));
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let mut link = Link::new(Pipe::new());
+        link.send_response(2, &Response::Pong { nonce: 42 }).unwrap();
+        link.send_response(3, &Response::Ack).unwrap();
+
+        let frame = link.recv_response().unwrap();
+        assert_eq!(frame.seq, 2);
+        assert!(matches!(frame.msg, Response::Pong { nonce: 42 }));
+        let frame = link.recv_response().unwrap();
+        assert_eq!(frame.seq, 3);
+        assert!(matches!(frame.msg, Response::Ack));
+    }
+
+    #[test]
+    fn corrupted_frame_is_reported_and_skipped() {
+        let mut link = Link::new(Pipe::new());
+        link.send_command(1, &Command::Ping { nonce: 0x0102_0304 }).unwrap();
+        link.send_command(2, &Command::ReadSensors).unwrap();
+        link.transport_mut().data[2] ^= 0x10;
+
+        assert!(matches!(link.recv_command(), Err(LinkError::Codec(CodecError::Checksum))));
+        let frame = link.recv_command().unwrap();
+        assert_eq!(frame.seq, 2);
+        assert!(matches!(frame.msg, Command::ReadSensors));
+    }
+}
@@ -10,17 +10,201 @@
 #include <stdlib.h>
 
 
+/**
+ * The size of the private storage of CommandAccumulator in 64 bit words
+ */
+#define CYPROTO_ACCUMULATOR_WORDS 34
+
+/**
+ * The size of the buffers passed to every function, see cyproto_buffer_size
+ */
+#define CYPROTO_BYTES_MAX 256
+
+/**
+ * Capability bit advertising that commands are acknowledged with cyproto_ack or cyproto_nack
+ * as soon as they are parsed
+ */
+#define CYPROTO_CAP_ACK (1 << 12)
+
+/**
+ * Capability bit advertising support for the arc command
+ */
+#define CYPROTO_CAP_ARC (1 << 4)
+
+/**
+ * Capability bit advertising that crossing the field's edge is reported with cyproto_boundary
+ */
+#define CYPROTO_CAP_BOUNDARY (1 << 8)
+
+/**
+ * Capability bit advertising support for the get and set config commands
+ */
+#define CYPROTO_CAP_CONFIG (1 << 10)
+
+/**
+ * Capability bit advertising that log messages are sent
+ */
+#define CYPROTO_CAP_LOG (1 << 11)
+
+/**
+ * Capability bit advertising that ping commands are answered even while a command is running
+ */
+#define CYPROTO_CAP_PING (1 << 6)
+
+/**
+ * Capability bit advertising support for the scan command
+ */
+#define CYPROTO_CAP_SCAN (1 << 0)
+
+/**
+ * Capability bit advertising support for the read sensors command
+ */
+#define CYPROTO_CAP_SENSORS (1 << 7)
+
+/**
+ * Capability bit advertising support for the sequence command
+ */
+#define CYPROTO_CAP_SEQUENCE (1 << 9)
+
+/**
+ * Capability bit advertising that running commands can be interrupted by a stop command
+ */
+#define CYPROTO_CAP_STOP (1 << 1)
+
+/**
+ * Capability bit advertising support for the raw sensor sweep command
+ */
+#define CYPROTO_CAP_SWEEP (1 << 2)
+
+/**
+ * Capability bit advertising that telemetry is sent periodically with cyproto_telemetry
+ */
+#define CYPROTO_CAP_TELEMETRY (1 << 3)
+
+/**
+ * Capability bit advertising support for the set velocity command
+ */
+#define CYPROTO_CAP_TELEOP (1 << 5)
+
+/**
+ * The most bytes a command can take on the wire including its terminating zero byte
+ */
+#define CYPROTO_COMMAND_MAX_SIZE 105
+
+/**
+ * The maximum length of a configuration key not counting the nul terminator
+ */
+#define CYPROTO_CONFIG_KEY_MAX 16
+
+/**
+ * The size of the key in a config command including the nul terminator
+ */
+#define CYPROTO_CONFIG_KEY_SIZE (CYPROTO_CONFIG_KEY_MAX + 1)
+
+/**
+ * The maximum length of a log message not counting the nul terminator, longer messages are truncated
+ */
+#define CYPROTO_LOG_MAX 96
+
+/**
+ * The most bytes a response can take on the wire including its terminating zero byte
+ */
+#define CYPROTO_RESPONSE_MAX_SIZE 249
+
+/**
+ * The maximum number of objects in a single scan frame, see max_objects
+ */
+#define CYPROTO_SCAN_MAX 26
+
+/**
+ * The maximum number of steps in a sequence command
+ */
+#define CYPROTO_SEQUENCE_MAX 8
+
+/**
+ * The maximum number of readings in a single sweep frame, see cyproto_max_readings
+ */
+#define CYPROTO_SWEEP_MAX 26
+
+/**
+ * How many milliseconds to keep following a velocity command before stopping the robot
+ */
+#define CYPROTO_VELOCITY_TIMEOUT_MS 500
+
+/**
+ * One of the four cliff sensors under the front of the robot
+ */
+typedef enum CliffSensor {
+  CliffSensor_FrontLeft,
+  CliffSensor_Left,
+  CliffSensor_Right,
+  CliffSensor_FrontRight,
+} CliffSensor;
+
 typedef enum CyprotoError {
   None,
   BufferOverflow,
   Postcard,
+  /**
+   * The checksum of the frame did not match, a byte was corrupted on the way
+   */
+  Checksum,
 } CyprotoError;
 
-typedef struct DriveDone {
-  float total_distance;
-  bool bump_detected;
-  bool cliff_detected;
-} DriveDone;
+/**
+ * Why a drive ended
+ */
+typedef enum DriveTermination {
+  DriveTermination_Completed,
+  DriveTermination_BumpLeft,
+  DriveTermination_BumpRight,
+  DriveTermination_BumpBoth,
+  DriveTermination_CliffFrontLeft,
+  DriveTermination_CliffLeft,
+  DriveTermination_CliffRight,
+  DriveTermination_CliffFrontRight,
+  DriveTermination_BoundaryFrontLeft,
+  DriveTermination_BoundaryLeft,
+  DriveTermination_BoundaryRight,
+  DriveTermination_BoundaryFrontRight,
+  DriveTermination_StoppedByHost,
+  DriveTermination_Timeout,
+} DriveTermination;
+
+/**
+ * The reason a command could not be carried out, reported with cyproto_error
+ */
+typedef enum ErrorCode {
+  ErrorCode_Decode,
+  ErrorCode_Unsupported,
+  ErrorCode_InvalidArgument,
+  ErrorCode_Hardware,
+  ErrorCode_Other,
+  ErrorCode_Busy,
+} ErrorCode;
+
+/**
+ * How important a log message is, from most to least important
+ */
+typedef enum LogLevel {
+  LogLevel_Error,
+  LogLevel_Warn,
+  LogLevel_Info,
+  LogLevel_Debug,
+} LogLevel;
+
+/**
+ * The distance sensors used to find objects during a scan
+ */
+typedef enum ScanSensor {
+  ScanSensor_Ir,
+  ScanSensor_Ping,
+  ScanSensor_Both,
+} ScanSensor;
+
+typedef struct HelloCommand {
+  uint16_t version;
+} HelloCommand;
 
 typedef struct DriveCommand {
   float distance;
@@ -32,16 +216,138 @@ typedef struct TurnCommand {
   uint16_t speed;
 } TurnCommand;
 
+/**
+ * A scan from `start` to `end` using the given sensors
+ * `step` is the number of degrees to move the servo each time or 0 to use the default
+ */
 typedef struct ScanCommand {
   uint8_t start;
   uint8_t end;
+  uint8_t step;
+  enum ScanSensor sensor;
 } ScanCommand;
 
+typedef struct SweepCommand {
+  uint8_t start;
+  uint8_t end;
+  uint8_t step;
+} SweepCommand;
+
+/**
+ * Drive `distance` centimeters along a circle with the given `radius`
+ * a positive radius curves counter-clockwise (left) and a negative radius clockwise (right)
+ */
+typedef struct ArcCommand {
+  float radius;
+  float distance;
+  uint16_t speed;
+} ArcCommand;
+
+/**
+ * Move at `linear` centimeters per second while turning counter-clockwise at `angular`
+ * degrees per second, a velocity of zero stops the robot
+ */
+typedef struct VelocityCommand {
+  float linear;
+  float angular;
+} VelocityCommand;
+
+typedef struct PingCommand {
+  uint32_t nonce;
+} PingCommand;
+
+/**
+ * A single command run as part of a sequence
+ */
+typedef enum Step_Tag {
+  Step_Drive,
+  Step_Turn,
+  Step_Arc,
+  Step_Scan,
+} Step_Tag;
+
+typedef struct Step {
+  Step_Tag tag;
+  union {
+    struct {
+      struct DriveCommand drive;
+    };
+    struct {
+      struct TurnCommand turn;
+    };
+    struct {
+      struct ArcCommand arc;
+    };
+    struct {
+      struct ScanCommand scan;
+    };
+  };
+} Step;
+
+/**
+ * Steps to run back to back, only the first `size` steps are valid
+ */
+typedef struct SequenceCommand {
+  size_t size;
+  struct Step steps[CYPROTO_SEQUENCE_MAX];
+} SequenceCommand;
+
+/**
+ * A request to read or change a calibration value
+ * `key` is nul terminated, `value` is only used when changing the value
+ */
+typedef struct ConfigCommand {
+  char key[CYPROTO_CONFIG_KEY_SIZE];
+  float value;
+} ConfigCommand;
+
 typedef enum CommandRequest_Tag {
   Error,
+  Hello,
   Drive,
   Turn,
   Scan,
+  /**
+   * Interrupt the running command, while a drive, turn or scan is running keep
+   * parsing incoming commands and when this arrives finish early by sending the
+   * partial result with the sequence number of the stop
+   */
+  Stop,
+  Sweep,
+  Arc,
+  /**
+   * Start following the velocity and remember when it arrived, there is no response
+   * stop the robot once cyproto_deadman_expired says no velocity arrived in time
+   */
+  SetVelocity,
+  /**
+   * Answer right away with cyproto_pong passing back the nonce, even while a drive,
+   * turn or scan is running
+   */
+  Ping,
+  /**
+   * Read every sensor without moving and answer with cyproto_sensors
+   */
+  ReadSensors,
+  /**
+   * Run the steps in order answering each one as if it was sent on its own using the
+   * sequence number of the sequence, then answer with cyproto_sequence_done
+   * end early if a drive, turn or arc falls short, if a step is rejected answer with
+   * cyproto_error and skip cyproto_sequence_done
+   */
+  Sequence,
+  /**
+   * Answer with cyproto_get_config
+   */
+  GetConfig,
+  /**
+   * Answer with cyproto_set_config
+   */
+  SetConfig,
+  /**
+   * Returned by cyproto_accumulate when no whole command has arrived yet
+   */
+  Incomplete,
 } CommandRequest_Tag;
 
 typedef struct CommandRequest {
@@ -50,6 +356,9 @@ typedef struct CommandRequest {
     struct {
       enum CyprotoError error;
     };
+    struct {
+      struct HelloCommand hello;
+    };
     struct {
       struct DriveCommand drive;
     };
@@ -59,49 +368,412 @@ typedef struct CommandRequest {
     struct {
       struct ScanCommand scan;
     };
+    struct {
+      struct SweepCommand sweep;
+    };
+    struct {
+      struct ArcCommand arc;
+    };
+    struct {
+      struct VelocityCommand set_velocity;
+    };
+    struct {
+      struct PingCommand ping;
+    };
+    struct {
+      struct SequenceCommand sequence;
+    };
+    struct {
+      struct ConfigCommand get_config;
+    };
+    struct {
+      struct ConfigCommand set_config;
+    };
   };
 } CommandRequest;
 
+/**
+ * Collects incoming bytes until a whole command has arrived
+ * the contents are private, set it up with cyproto_accumulator_init before use
+ */
+typedef struct CommandAccumulator {
+  uint64_t storage[CYPROTO_ACCUMULATOR_WORDS];
+} CommandAccumulator;
+
+/**
+ * Where the robot thinks it is relative to where it started
+ * the robot starts at the origin facing along the positive y axis
+ * `x` and `y` are in centimeters and `heading` is in degrees counter-clockwise
+ */
+typedef struct Pose {
+  float x;
+  float y;
+  float heading;
+} Pose;
+
+/**
+ * The result of an arc, `has_pose` and `pose` work the same as in DriveDone
+ */
+typedef struct ArcDone {
+  float total_distance;
+  float total_angle;
+  bool has_pose;
+  struct Pose pose;
+} ArcDone;
+
+/**
+ * The result of a drive, use DriveTermination_StoppedByHost when answering a stop
+ * set `has_pose` if `pose` holds where the robot's odometry puts it after the drive
+ */
+typedef struct DriveDone {
+  float total_distance;
+  enum DriveTermination termination;
+  bool has_pose;
+  struct Pose pose;
+} DriveDone;
+
+/**
+ * Read the calibration value called `key` into `value`
+ * return false if there is no such value
+ * `user` is the pointer that was passed along with the callback
+ */
+typedef bool (*ConfigGetFn)(void *user, const char *key, float *value);
+
 typedef struct ObjectData {
   float distance;
   uint8_t angle;
   float width;
 } ObjectData;
 
+/**
+ * A piece of a scan result, `size` must be at most max_objects()
+ * `total` is the number of chunks in the scan or 0 if it is not known yet
+ */
+typedef struct ScanChunk {
+  uint16_t index;
+  uint16_t total;
+  size_t size;
+  const struct ObjectData *objects;
+} ScanChunk;
+
 typedef struct ScanDone {
   size_t size;
   const struct ObjectData *objects;
 } ScanDone;
 
+/**
+ * A one-off reading of every sensor on the robot
+ * `cliff` holds the raw front left, left, right and front right cliff sensor readings
+ * `ir_distance` and `ping_distance` are read at the current `servo_angle`
+ */
+typedef struct Sensors {
+  bool bump_left;
+  bool bump_right;
+  uint16_t cliff[4];
+  uint8_t servo_angle;
+  float ir_distance;
+  float ping_distance;
+  float battery_voltage;
+} Sensors;
+
+/**
+ * Change the calibration value called `key`
+ * return false if there is no such value or `value` is not allowed
+ * `user` is the pointer that was passed along with the callback
+ */
+typedef bool (*ConfigSetFn)(void *user, const char *key, float value);
+
+typedef struct SweepData {
+  uint8_t angle;
+  float ir_distance;
+  float ping_distance;
+} SweepData;
+
+/**
+ * A piece of the readings of a sweep, `size` must be at most cyproto_max_readings()
+ * `total` is the number of chunks in the sweep or 0 if it is not known yet
+ */
+typedef struct SweepChunk {
+  uint16_t index;
+  uint16_t total;
+  size_t size;
+  const struct SweepData *readings;
+} SweepChunk;
+
+/**
+ * The state of the robot's hardware
+ * `cliff` holds the raw front left, left, right and front right cliff sensor readings
+ */
+typedef struct Telemetry {
+  float battery_voltage;
+  int32_t left_encoder;
+  int32_t right_encoder;
+  uint16_t cliff[4];
+  struct Pose pose;
+} Telemetry;
+
+/**
+ * The result of a turn, `has_pose` and `pose` work the same as in DriveDone
+ */
 typedef struct TurnDone {
   float total_angle;
+  bool has_pose;
+  struct Pose pose;
 } TurnDone;
 
+/**
+ * Feed `len` bytes received from the host into the accumulator
+ * as soon as a whole command has arrived it is parsed like cyproto_parse_command does, and
+ * the number of bytes used is written to `consumed` so the rest can be fed afterwards
+ * returns Incomplete if every byte was used without completing a command, or an error with
+ * BufferOverflow if a command was longer than cyproto_buffer_size() and had to be dropped
+ */
+struct CommandRequest cyproto_accumulate(struct CommandAccumulator *acc,
+                                         const uint8_t *data,
+                                         size_t len,
+                                         size_t *consumed,
+                                         uint16_t *seq);
+
+/**
+ * Set up an accumulator, this must be called before it is used and
+ * can be called again to throw away a partially received command
+ */
+void cyproto_accumulator_init(struct CommandAccumulator *acc);
+
+/**
+ * Serialize the acknowledgement that a command was received and is being carried out
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_ack(uint16_t seq, uint8_t *buf);
+
+/**
+ * Serialize an arc result struct into the provided buffer
+ * `seq` is the sequence number of the arc command being answered
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_arc_done(uint16_t seq, struct ArcDone val, uint8_t *buf);
+
+/**
+ * Serialize a report that a cliff sensor crossed the white tape at the edge of the field
+ * this can be sent at any time, it does not answer a command
+ * `signal` is the raw reading of the sensor that saw the tape
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_boundary(enum CliffSensor sensor, uint16_t signal, uint8_t *buf);
+
 /**
  * Get the expected buffer size for serializing and deserializing data
  * make sure the buffer has exactly cyproto_buffer_size() elements
  */
 size_t cyproto_buffer_size(void);
 
+/**
+ * Check whether the robot has gone too long without a velocity command and must stop
+ * `last_ms` is when the last velocity command arrived and `now_ms` is the current time,
+ * both are from the same millisecond clock which is allowed to wrap around
+ */
+bool cyproto_deadman_expired(uint32_t last_ms, uint32_t now_ms);
+
 /**
  * Serialize a drive result struct into the provided buffer
+ * `seq` is the sequence number of the drive command being answered
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_drive_done(uint16_t seq, struct DriveDone val, uint8_t *buf);
+
+/**
+ * Serialize how far a running drive has gotten so far into the provided buffer
+ * this can be sent any number of times before the drive is done
+ * `seq` is the sequence number of the drive command being run
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_drive_progress(uint16_t seq, float distance_so_far, uint8_t *buf);
+
+/**
+ * Serialize an error telling the host that a command could not be carried out
+ * `seq` is the sequence number of the rejected command, or 0 if it is unknown
+ * `detail` is an optional nul terminated message, it is truncated to fit and may be NULL
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_error(uint16_t seq, enum ErrorCode code, const char *detail, uint8_t *buf);
+
+/**
+ * Look up a calibration value with the `get` callback and serialize it into the provided buffer
+ * an error is serialized instead if `get` does not know the key
+ * `user` is passed along to the callback untouched and may be NULL
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_get_config(uint16_t seq,
+                          struct ConfigCommand cmd,
+                          ConfigGetFn get,
+                          void *user,
+                          uint8_t *buf);
+
+/**
+ * Serialize the answer to a hello command into the provided buffer
+ * `capabilities` is a combination of the CYPROTO_CAP_* flags for the supported commands
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_hello(uint16_t seq, uint32_t capabilities, uint8_t *buf);
+
+/**
+ * Serialize a message for the host into the provided buffer, it is sent on its own and not
+ * as the answer to a command so it can be sent at any time
+ * `message` is nul terminated and truncated to CYPROTO_LOG_MAX bytes
  * make sure the buffer has exactly cyproto_buffer_size() elements
  */
-size_t cyproto_drive_done(struct DriveDone val, uint8_t *buf);
+size_t cyproto_log(enum LogLevel level, const char *message, uint8_t *buf);
 
-struct CommandRequest cyproto_parse_command(uint8_t *buf);
+/**
+ * Get the maximum number of sweep readings that fit in a single chunk
+ */
+size_t cyproto_max_readings(void);
+
+/**
+ * Serialize the refusal of a command, nothing else should be sent for the command afterwards
+ * `seq` is the sequence number of the refused command, or 0 if it is unknown
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_nack(uint16_t seq, enum ErrorCode reason, uint8_t *buf);
+
+/**
+ * Whether a parsed command must be answered with cyproto_ack or cyproto_nack
+ * before it is carried out, when CYPROTO_CAP_ACK is advertised
+ * this is false for Error and Incomplete, commands that could not be parsed should
+ * be answered with cyproto_nack
+ */
+bool cyproto_needs_ack(const struct CommandRequest *request);
+
+/**
+ * Parse a command out of the provided buffer
+ * the sequence number of the command is written to `seq` and must be passed back
+ * to the function that serializes the result of the command, if the sequence number
+ * could not be read or the frame was corrupted it is set to 0
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+struct CommandRequest cyproto_parse_command(uint8_t *buf, uint16_t *seq);
+
+/**
+ * Serialize the answer to a ping command into the provided buffer
+ * `nonce` must be the nonce that came with the ping
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_pong(uint16_t seq, uint32_t nonce, uint8_t *buf);
+
+/**
+ * Get the version of the protocol spoken by this build of the library
+ */
+uint16_t cyproto_protocol_version(void);
+
+/**
+ * Serialize one chunk of a scan result that is too large for cyproto_scan_done
+ * every chunk must be followed up by a call to cyproto_scan_end
+ * `seq` is the sequence number of the scan command being answered
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_scan_chunk(uint16_t seq, struct ScanChunk val, uint8_t *buf);
+
+/**
+ * Get the number of chunks needed to send `size` scan objects
+ */
+size_t cyproto_scan_chunks(size_t size);
 
 /**
  * Serialize a scan result struct into the provided buffer
+ * `seq` is the sequence number of the scan command being answered
+ * results with more than max_objects() objects must be sent with cyproto_scan_chunk
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_scan_done(uint16_t seq, struct ScanDone val, uint8_t *buf);
+
+/**
+ * Serialize the end of a chunked scan result
+ * `count` is the total number of objects sent in all of the chunks
+ * `seq` is the sequence number of the scan command being answered
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_scan_end(uint16_t seq, uint16_t count, uint8_t *buf);
+
+/**
+ * Serialize the answer to a read sensors command into the provided buffer
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_sensors(uint16_t seq, struct Sensors val, uint8_t *buf);
+
+/**
+ * Serialize the end of a sequence into the provided buffer
+ * `completed` is the number of steps that ran to completion
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_sequence_done(uint16_t seq, uint8_t completed, uint8_t *buf);
+
+/**
+ * Change a calibration value with the `set` callback then read it back with the `get` callback
+ * and serialize the value the robot ended up using into the provided buffer
+ * an error is serialized instead if either callback refuses the key or value
+ * `user` is passed along to both callbacks untouched and may be NULL
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_set_config(uint16_t seq,
+                          struct ConfigCommand cmd,
+                          ConfigSetFn set,
+                          ConfigGetFn get,
+                          void *user,
+                          uint8_t *buf);
+
+/**
+ * Serialize the answer to a stop command that arrived while nothing was running
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_stopped(uint16_t seq, uint8_t *buf);
+
+/**
+ * Serialize one chunk of the readings of a sweep
+ * every chunk must be followed up by a call to cyproto_sweep_end
+ * `seq` is the sequence number of the sweep command being answered
  * make sure the buffer has exactly cyproto_buffer_size() elements
  */
-size_t cyproto_scan_done(struct ScanDone val, uint8_t *buf);
+size_t cyproto_sweep_chunk(uint16_t seq, struct SweepChunk val, uint8_t *buf);
+
+/**
+ * Get the number of chunks needed to send `size` sweep readings
+ */
+size_t cyproto_sweep_chunks(size_t size);
+
+/**
+ * Serialize the end of a sweep
+ * `count` is the total number of readings sent in all of the chunks
+ * `seq` is the sequence number of the sweep command being answered
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_sweep_end(uint16_t seq, uint16_t count, uint8_t *buf);
+
+/**
+ * Get the number of readings a sweep command will take
+ */
+size_t cyproto_sweep_size(struct SweepCommand cmd);
+
+/**
+ * Serialize the state of the robot into the provided buffer
+ * telemetry can be sent at any time, it does not answer a command
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_telemetry(struct Telemetry val, uint8_t *buf);
 
 /**
  * Serialize a turn result struct into the provided buffer
+ * `seq` is the sequence number of the turn command being answered
+ * make sure the buffer has exactly cyproto_buffer_size() elements
+ */
+size_t cyproto_turn_done(uint16_t seq, struct TurnDone val, uint8_t *buf);
+
+/**
+ * Serialize how far a running turn has gotten so far into the provided buffer
+ * this can be sent any number of times before the turn is done
+ * `seq` is the sequence number of the turn command being run
  * make sure the buffer has exactly cyproto_buffer_size() elements
  */
-size_t cyproto_turn_done(struct TurnDone val, uint8_t *buf);
+size_t cyproto_turn_progress(uint16_t seq, float angle_so_far, uint8_t *buf);
 
 /**
  * Get the maximum number of scan objects that are allowed by the buffer size
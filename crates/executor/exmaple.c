@@ -4,13 +4,14 @@
 DriveDone drive(float distance, uint16_t speed) {
     return (DriveDone) {
         .total_distance = 0,
-        .bump_detected = false,
-        .cliff_detected = true,
+        .termination = DriveTermination_CliffFrontLeft,
+        .has_pose = false,
     };
 }
 TurnDone turn(float angle, uint16_t speed) {
     return (TurnDone) {
         .total_angle = 0,
+        .has_pose = false,
     };
 }
 void scan(uint8_t start_angle, uint8_t end_angle, uint8_t fidelity, ScanData data[]) {
@@ -1,9 +1,100 @@
 #![no_std]
+// every exported function takes buffers from C so raw pointer arguments are expected
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
 
 #[cfg(feature = "panic-abort")]
 extern crate panic_abort;
 
-use cyproto_core::{Command, Response, SCAN_MAX};
+use core::ffi::{c_char, c_void, CStr};
+
+use cyproto_core::{
+    codec::{self, CodecError, FeedResult, FrameAccumulator},
+    Capabilities, Command, Frame, Response, Seq, BYTES_MAX, DETAIL_MAX, LOG_MAX, SCAN_MAX, SWEEP_MAX,
+};
+
+/// Capability bit advertising support for the scan command
+pub const CYPROTO_CAP_SCAN: u32 = 1 << 0;
+/// Capability bit advertising that running commands can be interrupted by a stop command
+pub const CYPROTO_CAP_STOP: u32 = 1 << 1;
+/// Capability bit advertising support for the raw sensor sweep command
+pub const CYPROTO_CAP_SWEEP: u32 = 1 << 2;
+/// Capability bit advertising that telemetry is sent periodically with cyproto_telemetry
+pub const CYPROTO_CAP_TELEMETRY: u32 = 1 << 3;
+/// Capability bit advertising support for the arc command
+pub const CYPROTO_CAP_ARC: u32 = 1 << 4;
+/// Capability bit advertising support for the set velocity command
+pub const CYPROTO_CAP_TELEOP: u32 = 1 << 5;
+/// Capability bit advertising that ping commands are answered even while a command is running
+pub const CYPROTO_CAP_PING: u32 = 1 << 6;
+/// Capability bit advertising support for the read sensors command
+pub const CYPROTO_CAP_SENSORS: u32 = 1 << 7;
+/// Capability bit advertising that crossing the field's edge is reported with cyproto_boundary
+pub const CYPROTO_CAP_BOUNDARY: u32 = 1 << 8;
+/// Capability bit advertising support for the sequence command
+pub const CYPROTO_CAP_SEQUENCE: u32 = 1 << 9;
+/// Capability bit advertising support for the get and set config commands
+pub const CYPROTO_CAP_CONFIG: u32 = 1 << 10;
+/// Capability bit advertising that log messages are sent
+pub const CYPROTO_CAP_LOG: u32 = 1 << 11;
+/// Capability bit advertising that commands are acknowledged with cyproto_ack or cyproto_nack
+/// as soon as they are parsed
+pub const CYPROTO_CAP_ACK: u32 = 1 << 12;
+
+/// The size of the buffers passed to every function, see cyproto_buffer_size
+pub const CYPROTO_BYTES_MAX: usize = 256;
+
+/// The most bytes a command can take on the wire including its terminating zero byte
+pub const CYPROTO_COMMAND_MAX_SIZE: usize = 105;
+
+/// The most bytes a response can take on the wire including its terminating zero byte
+pub const CYPROTO_RESPONSE_MAX_SIZE: usize = 249;
+
+/// The maximum number of objects in a single scan frame, see max_objects
+pub const CYPROTO_SCAN_MAX: usize = 26;
+
+/// The maximum number of readings in a single sweep frame, see cyproto_max_readings
+pub const CYPROTO_SWEEP_MAX: usize = 26;
+
+/// The size of the private storage of CommandAccumulator in 64 bit words
+pub const CYPROTO_ACCUMULATOR_WORDS: usize = 34;
+
+/// The maximum length of a log message not counting the nul terminator, longer messages are truncated
+pub const CYPROTO_LOG_MAX: usize = 96;
+
+/// The maximum length of a configuration key not counting the nul terminator
+pub const CYPROTO_CONFIG_KEY_MAX: usize = 16;
+
+/// The size of the key in a config command including the nul terminator
+pub const CYPROTO_CONFIG_KEY_SIZE: usize = CYPROTO_CONFIG_KEY_MAX + 1;
+
+/// The maximum number of steps in a sequence command
+pub const CYPROTO_SEQUENCE_MAX: usize = 8;
+
+/// How many milliseconds to keep following a velocity command before stopping the robot
+pub const CYPROTO_VELOCITY_TIMEOUT_MS: u32 = 500;
+
+const _: () = assert!(CYPROTO_CAP_SCAN == Capabilities::SCAN.0);
+const _: () = assert!(CYPROTO_CAP_STOP == Capabilities::STOP.0);
+const _: () = assert!(CYPROTO_CAP_SWEEP == Capabilities::SWEEP.0);
+const _: () = assert!(CYPROTO_CAP_TELEMETRY == Capabilities::TELEMETRY.0);
+const _: () = assert!(CYPROTO_CAP_ARC == Capabilities::ARC.0);
+const _: () = assert!(CYPROTO_CAP_TELEOP == Capabilities::TELEOP.0);
+const _: () = assert!(CYPROTO_CAP_PING == Capabilities::PING.0);
+const _: () = assert!(CYPROTO_CAP_SENSORS == Capabilities::SENSORS.0);
+const _: () = assert!(CYPROTO_CAP_BOUNDARY == Capabilities::BOUNDARY.0);
+const _: () = assert!(CYPROTO_CAP_SEQUENCE == Capabilities::SEQUENCE.0);
+const _: () = assert!(CYPROTO_SEQUENCE_MAX == cyproto_core::SEQUENCE_MAX);
+const _: () = assert!(CYPROTO_CAP_CONFIG == Capabilities::CONFIG.0);
+const _: () = assert!(CYPROTO_CONFIG_KEY_MAX == cyproto_core::CONFIG_KEY_MAX);
+const _: () = assert!(CYPROTO_CAP_LOG == Capabilities::LOG.0);
+const _: () = assert!(CYPROTO_LOG_MAX == cyproto_core::LOG_MAX);
+const _: () = assert!(CYPROTO_CAP_ACK == Capabilities::ACK.0);
+const _: () = assert!(CYPROTO_VELOCITY_TIMEOUT_MS == cyproto_core::VELOCITY_TIMEOUT_MS);
+const _: () = assert!(CYPROTO_BYTES_MAX == BYTES_MAX);
+const _: () = assert!(CYPROTO_COMMAND_MAX_SIZE == cyproto_core::COMMAND_MAX_SIZE);
+const _: () = assert!(CYPROTO_RESPONSE_MAX_SIZE == cyproto_core::RESPONSE_MAX_SIZE);
+const _: () = assert!(CYPROTO_SCAN_MAX == SCAN_MAX);
+const _: () = assert!(CYPROTO_SWEEP_MAX == SWEEP_MAX);
 
 #[repr(C)]
 #[derive(Debug, Default)]
@@ -12,6 +103,62 @@ pub enum CyprotoError {
     None,
     BufferOverflow,
     Postcard,
+    /// The checksum of the frame did not match, a byte was corrupted on the way
+    Checksum,
+}
+
+/// The reason a command could not be carried out, reported with cyproto_error
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorCode {
+    Decode,
+    Unsupported,
+    InvalidArgument,
+    Hardware,
+    Other,
+    Busy,
+}
+
+impl From<ErrorCode> for cyproto_core::ErrorCode {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Decode => Self::Decode,
+            ErrorCode::Unsupported => Self::Unsupported,
+            ErrorCode::InvalidArgument => Self::InvalidArgument,
+            ErrorCode::Hardware => Self::Hardware,
+            ErrorCode::Other => Self::Other,
+            ErrorCode::Busy => Self::Busy,
+        }
+    }
+}
+
+/// How important a log message is, from most to least important
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl From<LogLevel> for cyproto_core::LogLevel {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Self::Error,
+            LogLevel::Warn => Self::Warn,
+            LogLevel::Info => Self::Info,
+            LogLevel::Debug => Self::Debug,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct HelloCommand {
+    pub version: u16,
 }
 
 #[repr(C)]
@@ -21,11 +168,78 @@ pub struct DriveCommand {
     pub speed: u16,
 }
 
+/// One of the four cliff sensors under the front of the robot
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum CliffSensor {
+    FrontLeft,
+    Left,
+    Right,
+    FrontRight,
+}
+
+impl From<CliffSensor> for cyproto_core::CliffSensor {
+    fn from(sensor: CliffSensor) -> Self {
+        match sensor {
+            CliffSensor::FrontLeft => Self::FrontLeft,
+            CliffSensor::Left => Self::Left,
+            CliffSensor::Right => Self::Right,
+            CliffSensor::FrontRight => Self::FrontRight,
+        }
+    }
+}
+
+/// Why a drive ended
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub enum DriveTermination {
+    Completed,
+    BumpLeft,
+    BumpRight,
+    BumpBoth,
+    CliffFrontLeft,
+    CliffLeft,
+    CliffRight,
+    CliffFrontRight,
+    BoundaryFrontLeft,
+    BoundaryLeft,
+    BoundaryRight,
+    BoundaryFrontRight,
+    StoppedByHost,
+    Timeout,
+}
+
+impl From<DriveTermination> for cyproto_core::DriveTermination {
+    fn from(termination: DriveTermination) -> Self {
+        match termination {
+            DriveTermination::Completed => Self::Completed,
+            DriveTermination::BumpLeft => Self::BumpLeft,
+            DriveTermination::BumpRight => Self::BumpRight,
+            DriveTermination::BumpBoth => Self::BumpBoth,
+            DriveTermination::CliffFrontLeft => Self::CliffFrontLeft,
+            DriveTermination::CliffLeft => Self::CliffLeft,
+            DriveTermination::CliffRight => Self::CliffRight,
+            DriveTermination::CliffFrontRight => Self::CliffFrontRight,
+            DriveTermination::BoundaryFrontLeft => Self::BoundaryFrontLeft,
+            DriveTermination::BoundaryLeft => Self::BoundaryLeft,
+            DriveTermination::BoundaryRight => Self::BoundaryRight,
+            DriveTermination::BoundaryFrontRight => Self::BoundaryFrontRight,
+            DriveTermination::StoppedByHost => Self::StoppedByHost,
+            DriveTermination::Timeout => Self::Timeout,
+        }
+    }
+}
+
+/// The result of a drive, use DriveTermination_StoppedByHost when answering a stop
+/// set `has_pose` if `pose` holds where the robot's odometry puts it after the drive
 #[repr(C)]
 pub struct DriveDone {
     pub total_distance: f32,
-    pub bump_detected: bool,
-    pub cliff_detected: bool,
+    pub termination: DriveTermination,
+    pub has_pose: bool,
+    pub pose: Pose,
 }
 
 #[repr(C)]
@@ -35,16 +249,44 @@ pub struct TurnCommand {
     pub speed: u16,
 }
 
+/// The result of a turn, `has_pose` and `pose` work the same as in DriveDone
 #[repr(C)]
 pub struct TurnDone {
     pub total_angle: f32,
+    pub has_pose: bool,
+    pub pose: Pose,
+}
+
+/// The distance sensors used to find objects during a scan
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub enum ScanSensor {
+    Ir,
+    Ping,
+    #[default]
+    Both,
 }
 
+impl From<cyproto_core::ScanSensor> for ScanSensor {
+    fn from(sensor: cyproto_core::ScanSensor) -> Self {
+        match sensor {
+            cyproto_core::ScanSensor::Ir => Self::Ir,
+            cyproto_core::ScanSensor::Ping => Self::Ping,
+            cyproto_core::ScanSensor::Both => Self::Both,
+        }
+    }
+}
+
+/// A scan from `start` to `end` using the given sensors
+/// `step` is the number of degrees to move the servo each time or 0 to use the default
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct ScanCommand {
     pub start: u8,
     pub end: u8,
+    pub step: u8,
+    pub sensor: ScanSensor,
 }
 
 #[repr(C)]
@@ -62,111 +304,811 @@ pub struct ScanDone {
     pub objects: *const ObjectData,
 }
 
+/// A piece of a scan result, `size` must be at most max_objects()
+/// `total` is the number of chunks in the scan or 0 if it is not known yet
+#[repr(C)]
+#[derive(Debug)]
+pub struct ScanChunk {
+    pub index: u16,
+    pub total: u16,
+    pub size: usize,
+    pub objects: *const ObjectData,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SweepCommand {
+    pub start: u8,
+    pub end: u8,
+    pub step: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SweepData {
+    pub angle: u8,
+    pub ir_distance: f32,
+    pub ping_distance: f32,
+}
+
+/// A piece of the readings of a sweep, `size` must be at most cyproto_max_readings()
+/// `total` is the number of chunks in the sweep or 0 if it is not known yet
+#[repr(C)]
+#[derive(Debug)]
+pub struct SweepChunk {
+    pub index: u16,
+    pub total: u16,
+    pub size: usize,
+    pub readings: *const SweepData,
+}
+
+/// Drive `distance` centimeters along a circle with the given `radius`
+/// a positive radius curves counter-clockwise (left) and a negative radius clockwise (right)
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct ArcCommand {
+    pub radius: f32,
+    pub distance: f32,
+    pub speed: u16,
+}
+
+/// The result of an arc, `has_pose` and `pose` work the same as in DriveDone
+#[repr(C)]
+pub struct ArcDone {
+    pub total_distance: f32,
+    pub total_angle: f32,
+    pub has_pose: bool,
+    pub pose: Pose,
+}
+
+/// Move at `linear` centimeters per second while turning counter-clockwise at `angular`
+/// degrees per second, a velocity of zero stops the robot
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct VelocityCommand {
+    pub linear: f32,
+    pub angular: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct PingCommand {
+    pub nonce: u32,
+}
+
+/// Where the robot thinks it is relative to where it started
+/// the robot starts at the origin facing along the positive y axis
+/// `x` and `y` are in centimeters and `heading` is in degrees counter-clockwise
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub heading: f32,
+}
+
+impl From<Pose> for cyproto_core::Pose {
+    fn from(pose: Pose) -> Self {
+        let Pose { x, y, heading } = pose;
+        Self { x, y, heading }
+    }
+}
+
+/// The state of the robot's hardware
+/// `cliff` holds the raw front left, left, right and front right cliff sensor readings
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Telemetry {
+    pub battery_voltage: f32,
+    pub left_encoder: i32,
+    pub right_encoder: i32,
+    pub cliff: [u16; 4],
+    pub pose: Pose,
+}
+
+/// A one-off reading of every sensor on the robot
+/// `cliff` holds the raw front left, left, right and front right cliff sensor readings
+/// `ir_distance` and `ping_distance` are read at the current `servo_angle`
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Sensors {
+    pub bump_left: bool,
+    pub bump_right: bool,
+    pub cliff: [u16; 4],
+    pub servo_angle: u8,
+    pub ir_distance: f32,
+    pub ping_distance: f32,
+    pub battery_voltage: f32,
+}
+
+/// A single command run as part of a sequence
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug)]
+pub enum Step {
+    Drive(DriveCommand),
+    Turn(TurnCommand),
+    Arc(ArcCommand),
+    Scan(ScanCommand),
+}
+
+impl From<cyproto_core::Step> for Step {
+    fn from(step: cyproto_core::Step) -> Self {
+        match step {
+            cyproto_core::Step::Drive { distance, speed } => Self::Drive(DriveCommand { distance, speed }),
+            cyproto_core::Step::Turn { angle, speed } => Self::Turn(TurnCommand { angle, speed }),
+            cyproto_core::Step::Arc { radius, distance, speed } => {
+                Self::Arc(ArcCommand { radius, distance, speed })
+            }
+            cyproto_core::Step::Scan { start, end, step, sensor } => Self::Scan(ScanCommand {
+                start,
+                end,
+                step: step.unwrap_or(0),
+                sensor: sensor.into(),
+            }),
+        }
+    }
+}
+
+/// Steps to run back to back, only the first `size` steps are valid
+#[repr(C)]
+#[derive(Debug)]
+pub struct SequenceCommand {
+    pub size: usize,
+    pub steps: [Step; CYPROTO_SEQUENCE_MAX],
+}
+
+/// A request to read or change a calibration value
+/// `key` is nul terminated, `value` is only used when changing the value
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct ConfigCommand {
+    pub key: [c_char; CYPROTO_CONFIG_KEY_SIZE],
+    pub value: f32,
+}
+
+impl ConfigCommand {
+    /// Build a request for `key`, the key is truncated if it is longer than CYPROTO_CONFIG_KEY_MAX
+    pub fn new(key: &str, value: f32) -> Self {
+        let mut cmd = Self { value, ..Default::default() };
+        for (dst, src) in cmd.key.iter_mut().take(CYPROTO_CONFIG_KEY_MAX).zip(key.bytes()) {
+            *dst = src as c_char;
+        }
+        cmd
+    }
+}
+
+/// Read the calibration value called `key` into `value`
+/// return false if there is no such value
+/// `user` is the pointer that was passed along with the callback
+pub type ConfigGetFn = extern "C" fn(user: *mut c_void, key: *const c_char, value: *mut f32) -> bool;
+
+/// Change the calibration value called `key`
+/// return false if there is no such value or `value` is not allowed
+/// `user` is the pointer that was passed along with the callback
+pub type ConfigSetFn = extern "C" fn(user: *mut c_void, key: *const c_char, value: f32) -> bool;
+
 #[repr(C)]
 #[derive(Debug)]
 pub enum CommandRequest {
     Error(CyprotoError),
+    Hello(HelloCommand),
     Drive(DriveCommand),
     Turn(TurnCommand),
     Scan(ScanCommand),
+    /// Interrupt the running command, while a drive, turn or scan is running keep
+    /// parsing incoming commands and when this arrives finish early by sending the
+    /// partial result with the sequence number of the stop
+    Stop,
+    Sweep(SweepCommand),
+    Arc(ArcCommand),
+    /// Start following the velocity and remember when it arrived, there is no response
+    /// stop the robot once cyproto_deadman_expired says no velocity arrived in time
+    SetVelocity(VelocityCommand),
+    /// Answer right away with cyproto_pong passing back the nonce, even while a drive,
+    /// turn or scan is running
+    Ping(PingCommand),
+    /// Read every sensor without moving and answer with cyproto_sensors
+    ReadSensors,
+    /// Run the steps in order answering each one as if it was sent on its own using the
+    /// sequence number of the sequence, then answer with cyproto_sequence_done
+    /// end early if a drive, turn or arc falls short, if a step is rejected answer with
+    /// cyproto_error and skip cyproto_sequence_done
+    Sequence(SequenceCommand),
+    /// Answer with cyproto_get_config
+    GetConfig(ConfigCommand),
+    /// Answer with cyproto_set_config
+    SetConfig(ConfigCommand),
+    /// Returned by cyproto_accumulate when no whole command has arrived yet
+    Incomplete,
+}
+
+/// Collects incoming bytes until a whole command has arrived
+/// the contents are private, set it up with cyproto_accumulator_init before use
+#[repr(C)]
+pub struct CommandAccumulator {
+    storage: [u64; CYPROTO_ACCUMULATOR_WORDS],
 }
 
+const _: () = assert!(core::mem::size_of::<FrameAccumulator>() <= core::mem::size_of::<CommandAccumulator>());
+const _: () = assert!(core::mem::align_of::<FrameAccumulator>() <= core::mem::align_of::<CommandAccumulator>());
+
+impl CommandAccumulator {
+    fn frames(&mut self) -> &mut FrameAccumulator {
+        // the storage is large and aligned enough, see the asserts above
+        unsafe { &mut *(self.storage.as_mut_ptr() as *mut FrameAccumulator) }
+    }
+}
+
+/// Set up an accumulator, this must be called before it is used and
+/// can be called again to throw away a partially received command
+#[no_mangle]
+pub extern "C" fn cyproto_accumulator_init(acc: &mut CommandAccumulator) {
+    unsafe { (acc.storage.as_mut_ptr() as *mut FrameAccumulator).write(FrameAccumulator::new()) };
+}
+
+/// Feed `len` bytes received from the host into the accumulator
+/// as soon as a whole command has arrived it is parsed like cyproto_parse_command does, and
+/// the number of bytes used is written to `consumed` so the rest can be fed afterwards
+/// returns Incomplete if every byte was used without completing a command, or an error with
+/// BufferOverflow if a command was longer than cyproto_buffer_size() and had to be dropped
+#[no_mangle]
+pub extern "C" fn cyproto_accumulate(
+    acc: &mut CommandAccumulator,
+    data: *const u8,
+    len: usize,
+    consumed: &mut usize,
+    seq: *mut u16,
+) -> CommandRequest {
+    let data = unsafe { core::slice::from_raw_parts(data, len) };
+    *consumed = len;
+    match acc.frames().feed(data) {
+        FeedResult::Consumed => CommandRequest::Incomplete,
+        FeedResult::Frame { frame, remaining } => {
+            *consumed = len - remaining.len();
+            parse(frame, seq)
+        }
+        FeedResult::Overflow { remaining } => {
+            *consumed = len - remaining.len();
+            if let Some(seq) = unsafe { seq.as_mut() } {
+                *seq = cyproto_core::NO_SEQ;
+            }
+            CommandRequest::Error(CyprotoError::BufferOverflow)
+        }
+    }
+}
+
+/// Parse a command out of the provided buffer
+/// the sequence number of the command is written to `seq` and must be passed back
+/// to the function that serializes the result of the command, if the sequence number
+/// could not be read or the frame was corrupted it is set to 0
+/// make sure the buffer has exactly cyproto_buffer_size() elements
 #[no_mangle]
-pub extern "C" fn cyproto_parse_command(buf: *mut u8) -> CommandRequest {
+pub extern "C" fn cyproto_parse_command(buf: *mut u8, seq: *mut u16) -> CommandRequest {
     let buf_size = cyproto_buffer_size();
     let buf = unsafe { core::slice::from_raw_parts_mut(buf, buf_size) };
+    parse(buf, seq)
+}
+
+/// Parse a single encoded frame, see cyproto_parse_command
+fn parse(buf: &mut [u8], seq: *mut u16) -> CommandRequest {
+    let mut seq = unsafe { seq.as_mut() };
+    if let Some(seq) = &mut seq {
+        **seq = cyproto_core::NO_SEQ;
+    }
+
+    let buf = match codec::open(buf) {
+        Ok(buf) => buf,
+        Err(CodecError::Checksum) => return CommandRequest::Error(CyprotoError::Checksum),
+        Err(_) => return CommandRequest::Error(CyprotoError::Postcard),
+    };
+
+    // the sequence number is read on its own so it is still known if the command is invalid
+    let frame_seq = postcard::take_from_bytes::<Seq>(buf)
+        .map(|(seq, _)| seq)
+        .unwrap_or(cyproto_core::NO_SEQ);
+    if let Some(seq) = seq {
+        *seq = frame_seq;
+    }
 
-    let res: Result<Command, _> = postcard::from_bytes_cobs(buf);
-    match res {
-        Ok(Command::Drive { distance, speed }) => {
+    let res: Result<Frame<Command>, _> = postcard::from_bytes(buf);
+    let frame = match res {
+        Ok(frame) => frame,
+        Err(_) => return CommandRequest::Error(CyprotoError::Postcard),
+    };
+
+    match frame.msg {
+        Command::Hello { version } => {
+            CommandRequest::Hello(HelloCommand {
+                version,
+            })
+        }
+        Command::Drive { distance, speed } => {
             CommandRequest::Drive(DriveCommand {
                 distance,
                 speed,
             })
         }
-        Ok(Command::Turn { angle, speed }) => {
+        Command::Turn { angle, speed } => {
             CommandRequest::Turn(TurnCommand {
                 angle,
                 speed
             })
         }
-        Ok(Command::Scan { start, end }) => {
+        Command::Scan { start, end, step, sensor } => {
             CommandRequest::Scan(ScanCommand {
                 start,
                 end,
+                step: step.unwrap_or(0),
+                sensor: sensor.into(),
+            })
+        }
+        Command::Stop => CommandRequest::Stop,
+        Command::Sweep { start, end, step } => {
+            CommandRequest::Sweep(SweepCommand {
+                start,
+                end,
+                step,
+            })
+        }
+        Command::Arc { radius, distance, speed } => {
+            CommandRequest::Arc(ArcCommand {
+                radius,
+                distance,
+                speed,
             })
         }
-        Err(_) => {
-            CommandRequest::Error(CyprotoError::Postcard)
+        Command::SetVelocity { linear, angular } => {
+            CommandRequest::SetVelocity(VelocityCommand {
+                linear,
+                angular,
+            })
+        }
+        Command::Ping { nonce } => CommandRequest::Ping(PingCommand { nonce }),
+        Command::ReadSensors => CommandRequest::ReadSensors,
+        Command::GetConfig { key } => CommandRequest::GetConfig(ConfigCommand::new(&key, 0.)),
+        Command::SetConfig { key, value } => CommandRequest::SetConfig(ConfigCommand::new(&key, value)),
+        Command::Sequence(steps) => {
+            let size = steps.len();
+            let mut steps = steps.into_iter().map(Step::from);
+            CommandRequest::Sequence(SequenceCommand {
+                size,
+                steps: core::array::from_fn(|_| {
+                    steps.next().unwrap_or(Step::Drive(DriveCommand::default()))
+                }),
+            })
         }
     }
 }
 
+/// Get the version of the protocol spoken by this build of the library
+#[no_mangle]
+pub const extern "C" fn cyproto_protocol_version() -> u16 {
+    cyproto_core::PROTOCOL_VERSION
+}
+
 /// Get the expected buffer size for serializing and deserializing data
 /// make sure the buffer has exactly cyproto_buffer_size() elements
 #[no_mangle]
 pub const extern "C" fn cyproto_buffer_size() -> usize {
-    return cyproto_core::BYTES_MAX;
+    cyproto_core::BYTES_MAX
 }
 
 /// Get the maximum number of scan objects that are allowed by the buffer size
 /// make sure the buffer has exactly cyproto_buffer_size() elements
 #[no_mangle]
 pub extern "C" fn max_objects() -> usize {
-    return cyproto_core::SCAN_MAX;
+    cyproto_core::SCAN_MAX
 }
 
-/// Serialize a drive result struct into the provided buffer
-/// make sure the buffer has exactly cyproto_buffer_size() elements
+/// Get the maximum number of sweep readings that fit in a single chunk
+#[no_mangle]
+pub extern "C" fn cyproto_max_readings() -> usize {
+    SWEEP_MAX
+}
+
+/// Check whether the robot has gone too long without a velocity command and must stop
+/// `last_ms` is when the last velocity command arrived and `now_ms` is the current time,
+/// both are from the same millisecond clock which is allowed to wrap around
 #[no_mangle]
-pub extern "C" fn cyproto_drive_done(val: DriveDone, buf: *mut u8) -> usize {
+pub const extern "C" fn cyproto_deadman_expired(last_ms: u32, now_ms: u32) -> bool {
+    now_ms.wrapping_sub(last_ms) >= CYPROTO_VELOCITY_TIMEOUT_MS
+}
+
+/// Serialize a response along with its sequence number into the provided buffer
+fn write_response(seq: Seq, res: Response, buf: *mut u8) -> usize {
     let buf_size = cyproto_buffer_size();
     let buf = unsafe { core::slice::from_raw_parts_mut(buf, buf_size) };
 
-    let DriveDone { total_distance, cliff_detected, bump_detected } = val;
-    let res = Response::DriveDone { total_distance, bump_detected, cliff_detected };
+    codec::encode(&Frame::new(seq, res), buf).unwrap_or(0)
+}
+
+/// Serialize the answer to a hello command into the provided buffer
+/// `capabilities` is a combination of the CYPROTO_CAP_* flags for the supported commands
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_hello(seq: u16, capabilities: u32, buf: *mut u8) -> usize {
+    let res = Response::Hello {
+        version: cyproto_core::PROTOCOL_VERSION,
+        capabilities: Capabilities(capabilities),
+        bytes_max: BYTES_MAX as u16,
+        scan_max: SCAN_MAX as u16,
+    };
+
+    write_response(seq, res, buf)
+}
+
+/// Whether a parsed command must be answered with cyproto_ack or cyproto_nack
+/// before it is carried out, when CYPROTO_CAP_ACK is advertised
+/// this is false for Error and Incomplete, commands that could not be parsed should
+/// be answered with cyproto_nack
+#[no_mangle]
+pub extern "C" fn cyproto_needs_ack(request: &CommandRequest) -> bool {
+    // the same commands as Command::acknowledged
+    !matches!(
+        request,
+        CommandRequest::Error(_)
+            | CommandRequest::Incomplete
+            | CommandRequest::Hello(_)
+            | CommandRequest::Ping(_)
+            | CommandRequest::SetVelocity(_)
+    )
+}
+
+/// Serialize the acknowledgement that a command was received and is being carried out
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_ack(seq: u16, buf: *mut u8) -> usize {
+    write_response(seq, Response::Ack, buf)
+}
+
+/// Serialize the refusal of a command, nothing else should be sent for the command afterwards
+/// `seq` is the sequence number of the refused command, or 0 if it is unknown
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_nack(seq: u16, reason: ErrorCode, buf: *mut u8) -> usize {
+    write_response(seq, Response::Nack { reason: reason.into() }, buf)
+}
+
+/// Serialize an error telling the host that a command could not be carried out
+/// `seq` is the sequence number of the rejected command, or 0 if it is unknown
+/// `detail` is an optional nul terminated message, it is truncated to fit and may be NULL
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_error(seq: u16, code: ErrorCode, detail: *const c_char, buf: *mut u8) -> usize {
+    let res = Response::Error { code: code.into(), detail: truncate::<DETAIL_MAX>(detail) };
+
+    write_response(seq, res, buf)
+}
+
+/// Serialize a message for the host into the provided buffer, it is sent on its own and not
+/// as the answer to a command so it can be sent at any time
+/// `message` is nul terminated and truncated to CYPROTO_LOG_MAX bytes
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_log(level: LogLevel, message: *const c_char, buf: *mut u8) -> usize {
+    let res = Response::Log { level: level.into(), message: truncate::<LOG_MAX>(message) };
+
+    write_response(cyproto_core::NO_SEQ, res, buf)
+}
+
+/// Copy as much of a nul terminated string as fits, NULL gives an empty string
+/// and invalid UTF-8 ends the string where it starts
+fn truncate<const N: usize>(text: *const c_char) -> heapless::String<N> {
+    if text.is_null() {
+        return heapless::String::new();
+    }
+    truncate_bytes(unsafe { CStr::from_ptr(text) }.to_bytes())
+}
+
+/// Copy as much of a string as fits, invalid UTF-8 ends the string where it starts
+fn truncate_bytes<const N: usize>(bytes: &[u8]) -> heapless::String<N> {
+    let mut res = heapless::String::new();
+    let text = match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(err) => core::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default(),
+    };
+    for c in text.chars() {
+        if res.push(c).is_err() {
+            break;
+        }
+    }
+    res
+}
+
+/// Serialize the answer to a stop command that arrived while nothing was running
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_stopped(seq: u16, buf: *mut u8) -> usize {
+    write_response(seq, Response::Stopped, buf)
+}
+
+/// Serialize the answer to a ping command into the provided buffer
+/// `nonce` must be the nonce that came with the ping
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_pong(seq: u16, nonce: u32, buf: *mut u8) -> usize {
+    write_response(seq, Response::Pong { nonce }, buf)
+}
+
+/// Serialize the answer to a read sensors command into the provided buffer
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_sensors(seq: u16, val: Sensors, buf: *mut u8) -> usize {
+    let Sensors {
+        bump_left,
+        bump_right,
+        cliff,
+        servo_angle,
+        ir_distance,
+        ping_distance,
+        battery_voltage,
+    } = val;
+    let res = Response::Sensors(cyproto_core::Sensors {
+        bump_left,
+        bump_right,
+        cliff,
+        servo_angle,
+        ir_distance,
+        ping_distance,
+        battery_voltage,
+    });
+
+    write_response(seq, res, buf)
+}
+
+/// Serialize a report that a cliff sensor crossed the white tape at the edge of the field
+/// this can be sent at any time, it does not answer a command
+/// `signal` is the raw reading of the sensor that saw the tape
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_boundary(sensor: CliffSensor, signal: u16, buf: *mut u8) -> usize {
+    let res = Response::Boundary { sensor: sensor.into(), signal };
+
+    write_response(cyproto_core::NO_SEQ, res, buf)
+}
 
-    postcard::to_slice_cobs(&res, buf)
-        .map(|v| v.len())
-        .unwrap_or(0)
+/// Serialize the end of a sequence into the provided buffer
+/// `completed` is the number of steps that ran to completion
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_sequence_done(seq: u16, completed: u8, buf: *mut u8) -> usize {
+    write_response(seq, Response::SequenceDone { completed }, buf)
+}
+
+/// Look up a calibration value with the `get` callback and serialize it into the provided buffer
+/// an error is serialized instead if `get` does not know the key
+/// `user` is passed along to the callback untouched and may be NULL
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_get_config(
+    seq: u16,
+    mut cmd: ConfigCommand,
+    get: ConfigGetFn,
+    user: *mut c_void,
+    buf: *mut u8,
+) -> usize {
+    // the callback must not read past the key even if C filled the whole array
+    cmd.key[CYPROTO_CONFIG_KEY_MAX] = 0;
+    let mut value = 0.;
+    if !get(user, cmd.key.as_ptr(), &mut value) {
+        let res = Response::Error {
+            code: cyproto_core::ErrorCode::InvalidArgument,
+            detail: "unknown config key".into(),
+        };
+        return write_response(seq, res, buf);
+    }
+    write_response(seq, Response::Config { key: config_key(&cmd), value }, buf)
+}
+
+/// Change a calibration value with the `set` callback then read it back with the `get` callback
+/// and serialize the value the robot ended up using into the provided buffer
+/// an error is serialized instead if either callback refuses the key or value
+/// `user` is passed along to both callbacks untouched and may be NULL
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_set_config(
+    seq: u16,
+    mut cmd: ConfigCommand,
+    set: ConfigSetFn,
+    get: ConfigGetFn,
+    user: *mut c_void,
+    buf: *mut u8,
+) -> usize {
+    cmd.key[CYPROTO_CONFIG_KEY_MAX] = 0;
+    if !set(user, cmd.key.as_ptr(), cmd.value) {
+        let res = Response::Error {
+            code: cyproto_core::ErrorCode::InvalidArgument,
+            detail: "config value refused".into(),
+        };
+        return write_response(seq, res, buf);
+    }
+    cyproto_get_config(seq, cmd, get, user, buf)
+}
+
+/// Get the key of a config command back as a string
+/// a key that fills the whole array without a nul terminator ends at the end of the array
+fn config_key(cmd: &ConfigCommand) -> cyproto_core::ConfigKey {
+    let bytes = cmd.key.map(|c| c as u8);
+    let key = CStr::from_bytes_until_nul(&bytes).map_or(&bytes[..], CStr::to_bytes);
+    truncate_bytes(key)
+}
+
+/// Serialize the state of the robot into the provided buffer
+/// telemetry can be sent at any time, it does not answer a command
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_telemetry(val: Telemetry, buf: *mut u8) -> usize {
+    let Telemetry { battery_voltage, left_encoder, right_encoder, cliff, pose } = val;
+    let res = Response::Telemetry(cyproto_core::Telemetry {
+        battery_voltage,
+        left_encoder,
+        right_encoder,
+        cliff,
+        pose: pose.into(),
+    });
+
+    write_response(cyproto_core::NO_SEQ, res, buf)
+}
+
+/// Serialize a drive result struct into the provided buffer
+/// `seq` is the sequence number of the drive command being answered
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_drive_done(seq: u16, val: DriveDone, buf: *mut u8) -> usize {
+    let DriveDone { total_distance, termination, has_pose, pose } = val;
+    let res = Response::DriveDone {
+        total_distance,
+        termination: termination.into(),
+        pose: has_pose.then(|| pose.into()),
+    };
+
+    write_response(seq, res, buf)
+}
+
+/// Serialize how far a running drive has gotten so far into the provided buffer
+/// this can be sent any number of times before the drive is done
+/// `seq` is the sequence number of the drive command being run
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_drive_progress(seq: u16, distance_so_far: f32, buf: *mut u8) -> usize {
+    write_response(seq, Response::DriveProgress { distance_so_far }, buf)
 }
 
 /// Serialize a turn result struct into the provided buffer
+/// `seq` is the sequence number of the turn command being answered
 /// make sure the buffer has exactly cyproto_buffer_size() elements
 #[no_mangle]
-pub extern "C" fn cyproto_turn_done(val: TurnDone, buf: *mut u8) -> usize {
-    let buf_size = cyproto_buffer_size();
-    let buf = unsafe { core::slice::from_raw_parts_mut(buf, buf_size) };
+pub extern "C" fn cyproto_turn_done(seq: u16, val: TurnDone, buf: *mut u8) -> usize {
+    let TurnDone { total_angle, has_pose, pose } = val;
+    let res = Response::TurnDone { total_angle, pose: has_pose.then(|| pose.into()) };
+
+    write_response(seq, res, buf)
+}
+
+/// Serialize how far a running turn has gotten so far into the provided buffer
+/// this can be sent any number of times before the turn is done
+/// `seq` is the sequence number of the turn command being run
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_turn_progress(seq: u16, angle_so_far: f32, buf: *mut u8) -> usize {
+    write_response(seq, Response::TurnProgress { angle_so_far }, buf)
+}
 
-    let TurnDone { total_angle } = val;
-    let res = Response::TurnDone { total_angle };
+/// Serialize an arc result struct into the provided buffer
+/// `seq` is the sequence number of the arc command being answered
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_arc_done(seq: u16, val: ArcDone, buf: *mut u8) -> usize {
+    let ArcDone { total_distance, total_angle, has_pose, pose } = val;
+    let res = Response::ArcDone {
+        total_distance,
+        total_angle,
+        pose: has_pose.then(|| pose.into()),
+    };
 
-    postcard::to_slice_cobs(&res, buf)
-        .map(|v| v.len())
-        .unwrap_or(0)
+    write_response(seq, res, buf)
 }
 
 /// Serialize a scan result struct into the provided buffer
+/// `seq` is the sequence number of the scan command being answered
+/// results with more than max_objects() objects must be sent with cyproto_scan_chunk
 /// make sure the buffer has exactly cyproto_buffer_size() elements
 #[no_mangle]
-pub extern "C" fn cyproto_scan_done(val: ScanDone, buf: *mut u8) -> usize {
-    let buf_size = cyproto_buffer_size();
-    let buf = unsafe { core::slice::from_raw_parts_mut(buf, buf_size) };
+pub extern "C" fn cyproto_scan_done(seq: u16, val: ScanDone, buf: *mut u8) -> usize {
+    if val.size > SCAN_MAX {
+        return 0;
+    }
+    let data = object_data(val.objects, val.size);
+    let res = Response::ScanDone { data };
+
+    write_response(seq, res, buf)
+}
+
+/// Get the number of chunks needed to send `size` scan objects
+#[no_mangle]
+pub extern "C" fn cyproto_scan_chunks(size: usize) -> usize {
+    size.div_ceil(SCAN_MAX)
+}
 
+/// Serialize one chunk of a scan result that is too large for cyproto_scan_done
+/// every chunk must be followed up by a call to cyproto_scan_end
+/// `seq` is the sequence number of the scan command being answered
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_scan_chunk(seq: u16, val: ScanChunk, buf: *mut u8) -> usize {
     if val.size > SCAN_MAX {
         return 0;
     }
-    let data = unsafe { core::slice::from_raw_parts(val.objects, val.size) };
+    let data = object_data(val.objects, val.size);
+    let res = Response::ScanChunk { index: val.index, total: val.total, data };
+
+    write_response(seq, res, buf)
+}
+
+/// Serialize the end of a chunked scan result
+/// `count` is the total number of objects sent in all of the chunks
+/// `seq` is the sequence number of the scan command being answered
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_scan_end(seq: u16, count: u16, buf: *mut u8) -> usize {
+    write_response(seq, Response::ScanEnd { count }, buf)
+}
+
+/// Get the number of readings a sweep command will take
+#[no_mangle]
+pub extern "C" fn cyproto_sweep_size(cmd: SweepCommand) -> usize {
+    if cmd.start > cmd.end || cmd.step == 0 {
+        return 0;
+    }
+    usize::from((cmd.end - cmd.start) / cmd.step) + 1
+}
+
+/// Get the number of chunks needed to send `size` sweep readings
+#[no_mangle]
+pub extern "C" fn cyproto_sweep_chunks(size: usize) -> usize {
+    size.div_ceil(SWEEP_MAX)
+}
+
+/// Serialize one chunk of the readings of a sweep
+/// every chunk must be followed up by a call to cyproto_sweep_end
+/// `seq` is the sequence number of the sweep command being answered
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_sweep_chunk(seq: u16, val: SweepChunk, buf: *mut u8) -> usize {
+    if val.size > SWEEP_MAX {
+        return 0;
+    }
+    let data = unsafe { core::slice::from_raw_parts(val.readings, val.size) };
+    let data = data.iter()
+        .map(|s| cyproto_core::SweepData {
+            angle: s.angle,
+            ir_distance: s.ir_distance,
+            ping_distance: s.ping_distance,
+        });
+    let data = heapless::Vec::from_iter(data);
+    let res = Response::SweepChunk { index: val.index, total: val.total, data };
+
+    write_response(seq, res, buf)
+}
+
+/// Serialize the end of a sweep
+/// `count` is the total number of readings sent in all of the chunks
+/// `seq` is the sequence number of the sweep command being answered
+/// make sure the buffer has exactly cyproto_buffer_size() elements
+#[no_mangle]
+pub extern "C" fn cyproto_sweep_end(seq: u16, count: u16, buf: *mut u8) -> usize {
+    write_response(seq, Response::SweepEnd { count }, buf)
+}
+
+/// Convert the scan objects from C, `size` must be at most SCAN_MAX
+fn object_data(objects: *const ObjectData, size: usize) -> heapless::Vec<cyproto_core::ObjectData, SCAN_MAX> {
+    let data = unsafe { core::slice::from_raw_parts(objects, size) };
     let data = data.iter()
         .map(|s| cyproto_core::ObjectData {
             angle: s.angle,
             distance: s.distance,
             width: s.width,
         });
-    let data = heapless::Vec::<_, SCAN_MAX>::from_iter(data);
-    let res = Response::ScanDone { data };
-
-    postcard::to_slice_cobs(&res, buf)
-        .map(|v| v.len())
-        .unwrap_or(0)
+    heapless::Vec::from_iter(data)
 }
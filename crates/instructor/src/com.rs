@@ -1,44 +1,196 @@
-use std::io::{self, Read, Write};
+use std::{
+    io::{self, Read, Write},
+    time::Instant,
+};
 
-use cyproto_core::{Command, Response};
+use bevy::prelude::Resource;
+use cyproto_core::{
+    transport::{Link, LinkError, StdIo},
+    Command, Frame, Response, Seq,
+};
 
-pub fn read_response(
-    stream: &mut crate::Socket,
-) -> Result<Option<Response>, Box<dyn std::error::Error>> {
-    let mut buffer = Vec::new();
+/// Reassembles scan or sweep results that the robot sent in multiple chunks
+#[derive(Debug, Resource)]
+pub struct ChunkAssembler<T> {
+    seq: Seq,
+    total: u16,
+    next_index: u16,
+    items: Vec<T>,
+}
 
+impl<T> Default for ChunkAssembler<T> {
+    fn default() -> Self {
+        Self {
+            seq: 0,
+            total: 0,
+            next_index: 0,
+            items: Vec::new(),
+        }
+    }
+}
 
-    // don't block until the first byte of data comes across the buffer
-    let mut byte_buf = [0; 1];
-    if let Err(err) = stream.0.read(&mut byte_buf) {
-        if err.kind() == io::ErrorKind::WouldBlock {
-            return Ok(None);
-        } else {
-            return Err(Box::new(err));
+impl<T: Clone> ChunkAssembler<T> {
+    /// Add a chunk of the result answering the command with the given sequence number
+    pub fn push(&mut self, seq: Seq, index: u16, total: u16, data: &[T]) -> Result<(), String> {
+        if index == 0 {
+            *self = Self {
+                seq,
+                total,
+                ..Default::default()
+            };
         }
+        if seq != self.seq || index != self.next_index {
+            let expected = self.next_index;
+            *self = Self::default();
+            return Err(format!("Expected chunk {expected} but got {index}"));
+        }
+        self.next_index += 1;
+        self.items.extend_from_slice(data);
+        Ok(())
     }
-    buffer.push(byte_buf[0]);
 
-    // the rest of the data should follow quickly after the first
-    while byte_buf[0] != 0 {
-        while let Err(err) = stream.0.read(&mut byte_buf) {
-            if err.kind() == io::ErrorKind::WouldBlock {
-                continue;
-            } else {
-                return Err(Box::new(err));
-            }
+    /// Finish the result returning all of the items in it
+    pub fn finish(&mut self, seq: Seq, count: u16) -> Result<Vec<T>, String> {
+        let assembler = std::mem::take(self);
+        if seq != assembler.seq {
+            return Err("Result ended without any chunks".to_owned());
+        }
+        if assembler.total != 0 && assembler.next_index != assembler.total {
+            return Err(format!(
+                "Result ended after {} of {} chunks",
+                assembler.next_index, assembler.total
+            ));
+        }
+        if usize::from(count) != assembler.items.len() {
+            return Err(format!(
+                "Result ended with {} of {count} items",
+                assembler.items.len()
+            ));
         }
-        buffer.push(byte_buf[0]);
+        Ok(assembler.items)
     }
-    let response: Response = postcard::from_bytes_cobs(&mut buffer)?;
-    Ok(Some(response))
 }
 
+/// Any stream of bytes the robot can be reached over
+pub trait Stream: Read + Write + Send + Sync {}
+
+impl<S: Read + Write + Send + Sync> Stream for S {}
+
+/// The framed connection to the robot, reads must not block
+pub type RobotLink = Link<StdIo<Box<dyn Stream>>>;
+
+/// Read the next frame from the robot if a whole one has arrived
+///
+/// Partial frames are kept until the rest arrives so this never waits on the robot.
+pub fn read_response(
+    stream: &mut crate::Socket,
+) -> Result<Option<Frame<Response>>, Box<dyn std::error::Error>> {
+    if stream.closed {
+        return Ok(None);
+    }
+
+    match stream.link.recv_response() {
+        Ok(frame) => Ok(Some(frame)),
+        Err(LinkError::Transport(err)) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(LinkError::Transport(err)) => Err(Box::new(err)),
+        Err(LinkError::Closed) => {
+            stream.closed = true;
+            Err(Box::new(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The robot closed the connection",
+            )))
+        }
+        Err(LinkError::Codec(err)) => Err(Box::new(err)),
+    }
+}
+
+/// Send a command to the robot returning the sequence number it was sent with
 pub fn send_command(
     stream: &mut crate::Socket,
     command: Command,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let encoded = postcard::to_stdvec_cobs(&command)?;
-    stream.0.write_all(&encoded)?;
-    Ok(())
+) -> Result<Seq, Box<dyn std::error::Error>> {
+    let seq = stream.next_seq();
+    // a robot without acknowledgements would have every command count as lost
+    let acknowledged = stream.acks && command.acknowledged();
+    stream.link.send_command(seq, &command)?;
+    if acknowledged {
+        stream.unacked = Some((seq, Instant::now()));
+    }
+    Ok(seq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_in_order() {
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(3, 0, 2, &[1, 2]).unwrap();
+        assembler.push(3, 1, 2, &[3]).unwrap();
+        assert_eq!(assembler.finish(3, 3).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn out_of_order_chunk_drops_the_result() {
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(3, 0, 3, &[1]).unwrap();
+        assert_eq!(assembler.push(3, 2, 3, &[3]).unwrap_err(), "Expected chunk 1 but got 2");
+        assert!(assembler.push(3, 1, 3, &[2]).is_err());
+        assert!(assembler.finish(3, 3).is_err());
+    }
+
+    #[test]
+    fn duplicate_chunk_drops_the_result() {
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(3, 0, 2, &[1]).unwrap();
+        assembler.push(3, 1, 2, &[2]).unwrap();
+        assert_eq!(assembler.push(3, 1, 2, &[2]).unwrap_err(), "Expected chunk 2 but got 1");
+        assert!(assembler.finish(3, 2).is_err());
+    }
+
+    #[test]
+    fn first_chunk_starts_over() {
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(3, 0, 2, &[1]).unwrap();
+        assembler.push(4, 0, 1, &[7]).unwrap();
+        assert_eq!(assembler.finish(4, 1).unwrap(), [7]);
+    }
+
+    #[test]
+    fn chunk_of_another_command_drops_the_result() {
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(3, 0, 2, &[1]).unwrap();
+        assert!(assembler.push(4, 1, 2, &[2]).is_err());
+        assert!(assembler.finish(3, 2).is_err());
+    }
+
+    #[test]
+    fn finish_before_every_chunk() {
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(3, 0, 3, &[1]).unwrap();
+        assembler.push(3, 1, 3, &[2]).unwrap();
+        assert_eq!(assembler.finish(3, 3).unwrap_err(), "Result ended after 2 of 3 chunks");
+    }
+
+    #[test]
+    fn finish_without_chunks() {
+        let mut assembler = ChunkAssembler::<u8>::default();
+        assert_eq!(assembler.finish(3, 0).unwrap_err(), "Result ended without any chunks");
+    }
+
+    #[test]
+    fn finish_with_missing_items() {
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(3, 0, 1, &[1, 2]).unwrap();
+        assert_eq!(assembler.finish(3, 3).unwrap_err(), "Result ended with 2 of 3 items");
+    }
+
+    #[test]
+    fn finish_resets() {
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(3, 0, 1, &[1]).unwrap();
+        assembler.finish(3, 1).unwrap();
+        assert!(assembler.finish(3, 1).is_err());
+    }
 }
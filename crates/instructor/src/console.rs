@@ -1,11 +1,11 @@
-use std::num::NonZeroU16;
+use std::num::{NonZeroU16, NonZeroU8};
 
 use bevy::prelude::*;
-use bevy_console::{AddConsoleCommand, ConsoleCommand, ConsoleConfiguration, ConsolePlugin};
-use clap::Parser;
-use cyproto_core::Command;
+use bevy_console::{AddConsoleCommand, ConsoleCommand, ConsoleConfiguration, ConsolePlugin, PrintConsoleLine};
+use clap::{Parser, ValueEnum};
+use cyproto_core::{Capabilities, Command, ConfigKey, ScanSensor, CONFIG_KEY_MAX, Step, Steps, SEQUENCE_MAX};
 
-use crate::{Socket, State};
+use crate::{RobotInfo, Socket, State};
 
 
 /// Drive the cybot
@@ -37,9 +37,44 @@ pub struct TurnCli {
     pub speed: NonZeroU16,
 }
 
+/// Drive the cybot along an arc
+///
+/// This command will drive the robot along a circle with the given radius
+/// a positive radius curves counter-clockwise (left) and a negative
+/// radius curves clockwise (right), a negative distance drives backwards
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "arc")]
+pub struct ArcCli {
+    #[arg(allow_negative_numbers = true)]
+    pub radius: f32,
+    #[arg(allow_negative_numbers = true)]
+    pub distance: f32,
+    #[arg(default_value_t = NonZeroU16::new(200).unwrap())]
+    pub speed: NonZeroU16,
+}
+
+/// The distance sensors the scan command can use
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SensorArg {
+    Ir,
+    Ping,
+    Both,
+}
+
+impl From<SensorArg> for ScanSensor {
+    fn from(sensor: SensorArg) -> Self {
+        match sensor {
+            SensorArg::Ir => ScanSensor::Ir,
+            SensorArg::Ping => ScanSensor::Ping,
+            SensorArg::Both => ScanSensor::Both,
+        }
+    }
+}
+
 /// Scan the field
 ///
 /// This command tells the robot to scan the field for objects
+/// a larger step makes the scan faster but less precise
 #[derive(Parser, ConsoleCommand)]
 #[command(name = "scan")]
 pub struct ScanCli {
@@ -47,8 +82,71 @@ pub struct ScanCli {
     pub start: u8,
     #[arg(default_value_t = 180)]
     pub end: u8,
+    #[arg(long)]
+    pub step: Option<NonZeroU8>,
+    #[arg(long, value_enum, default_value_t = SensorArg::Both)]
+    pub sensor: SensorArg,
+}
+
+/// Sweep the sensors
+///
+/// This command tells the robot to sweep its sensors across the field
+/// and send back the raw IR and ping readings at every step
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "sweep")]
+pub struct SweepCli {
+    #[arg(default_value_t = 0)]
+    pub start: u8,
+    #[arg(default_value_t = 180)]
+    pub end: u8,
+    #[arg(default_value_t = NonZeroU8::new(2).unwrap())]
+    pub step: NonZeroU8,
 }
 
+/// Read the cybot's sensors
+///
+/// This command reads every sensor once without moving
+/// so the hardware can be checked before a run
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "sensors")]
+pub struct SensorsCli;
+
+/// Read or change a calibration value on the cybot
+///
+/// This command prints the value of the key, or changes it
+/// when a value is given, for example:
+/// config wheel_factor 1.02
+/// changes are lost when the robot restarts
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "config")]
+pub struct ConfigCli {
+    pub key: String,
+    #[arg(allow_negative_numbers = true)]
+    pub value: Option<f32>,
+}
+
+/// Run several commands back to back
+///
+/// This command sends the steps to the robot all at once
+/// separate the steps with `;` and write each one like
+/// its own command, for example:
+/// sequence turn 90; drive 50; scan
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "sequence")]
+pub struct SequenceCli {
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    pub steps: Vec<String>,
+}
+
+/// Stop the cybot
+///
+/// This command stops whatever the robot is doing, unlike
+/// other commands it can be sent while a command is running.
+/// Pressing escape does the same thing.
+#[derive(Parser, ConsoleCommand)]
+#[command(name = "stop")]
+pub struct StopCli;
+
 
 /// Send the drive command to the robot
 fn do_drive(
@@ -66,7 +164,7 @@ fn do_drive(
         return;
     }
 
-    crate::com::send_command(
+    let seq = crate::com::send_command(
         &mut socket,
         Command::Drive {
             distance,
@@ -74,7 +172,11 @@ fn do_drive(
         },
     )
     .unwrap();
-    *state = State::SentDrive { distance };
+    *state = State::SentDrive {
+        seq,
+        distance,
+        progress: 0.,
+    };
 }
 
 /// Send the turn command to the robot
@@ -89,7 +191,7 @@ fn do_turn(mut cli: ConsoleCommand<TurnCli>, mut socket: ResMut<Socket>, mut sta
         return;
     }
 
-    crate::com::send_command(
+    let seq = crate::com::send_command(
         &mut socket,
         Command::Turn {
             angle,
@@ -97,23 +199,339 @@ fn do_turn(mut cli: ConsoleCommand<TurnCli>, mut socket: ResMut<Socket>, mut sta
         },
     )
     .unwrap();
-    *state = State::SentTurn { angle };
+    *state = State::SentTurn {
+        seq,
+        angle,
+        progress: 0.,
+    };
+}
+
+/// Send the arc command to the robot
+fn do_arc(
+    mut cli: ConsoleCommand<ArcCli>,
+    mut socket: ResMut<Socket>,
+    mut state: ResMut<State>,
+    robot: Res<RobotInfo>,
+) {
+    let ArcCli {
+        radius,
+        distance,
+        speed,
+    } = match cli.take() {
+        Some(Ok(cmd)) => cmd,
+        _ => return,
+    };
+
+    if !robot.capabilities.contains(Capabilities::ARC) {
+        cli.reply_failed("The robot does not support driving in arcs");
+        return;
+    }
+
+    if radius == 0. {
+        cli.reply_failed("The radius must not be zero, use turn to spin in place");
+        return;
+    }
+
+    if !matches!(*state, State::Normal) {
+        cli.reply_failed("Unable to run command while another command is being processed");
+        return;
+    }
+
+    let seq = crate::com::send_command(
+        &mut socket,
+        Command::Arc {
+            radius,
+            distance,
+            speed: speed.into(),
+        },
+    )
+    .unwrap();
+    *state = State::SentArc {
+        seq,
+        radius,
+        distance,
+    };
 }
 
 /// Send the scan command to the robot
-fn do_scan(mut cli: ConsoleCommand<ScanCli>, mut socket: ResMut<Socket>, mut state: ResMut<State>) {
-    let ScanCli { start, end } = match cli.take() {
+fn do_scan(
+    mut cli: ConsoleCommand<ScanCli>,
+    mut socket: ResMut<Socket>,
+    mut state: ResMut<State>,
+    robot: Res<RobotInfo>,
+) {
+    let ScanCli {
+        start,
+        end,
+        step,
+        sensor,
+    } = match cli.take() {
+        Some(Ok(cmd)) => cmd,
+        _ => return,
+    };
+
+    if !robot.capabilities.contains(Capabilities::SCAN) {
+        cli.reply_failed("The robot does not support scanning");
+        return;
+    }
+
+    if !matches!(*state, State::Normal) {
+        cli.reply_failed("Unable to run command while another command is being processed");
+        return;
+    }
+
+    let seq = crate::com::send_command(
+        &mut socket,
+        Command::Scan {
+            start,
+            end,
+            step: step.map(NonZeroU8::get),
+            sensor: sensor.into(),
+        },
+    )
+    .unwrap();
+    *state = State::SentScan { seq, start, end };
+}
+
+/// Send the sweep command to the robot
+fn do_sweep(
+    mut cli: ConsoleCommand<SweepCli>,
+    mut socket: ResMut<Socket>,
+    mut state: ResMut<State>,
+    robot: Res<RobotInfo>,
+) {
+    let SweepCli { start, end, step } = match cli.take() {
+        Some(Ok(cmd)) => cmd,
+        _ => return,
+    };
+
+    if !robot.capabilities.contains(Capabilities::SWEEP) {
+        cli.reply_failed("The robot does not support sweeping");
+        return;
+    }
+
+    if !matches!(*state, State::Normal) {
+        cli.reply_failed("Unable to run command while another command is being processed");
+        return;
+    }
+
+    let step = step.into();
+    let seq = crate::com::send_command(&mut socket, Command::Sweep { start, end, step }).unwrap();
+    *state = State::SentSweep { seq, start, end, step };
+}
+
+/// Send the read sensors command to the robot
+fn do_sensors(
+    mut cli: ConsoleCommand<SensorsCli>,
+    mut socket: ResMut<Socket>,
+    mut state: ResMut<State>,
+    robot: Res<RobotInfo>,
+) {
+    let SensorsCli = match cli.take() {
+        Some(Ok(cmd)) => cmd,
+        _ => return,
+    };
+
+    if !robot.capabilities.contains(Capabilities::SENSORS) {
+        cli.reply_failed("The robot does not support reading sensors");
+        return;
+    }
+
+    if !matches!(*state, State::Normal) {
+        cli.reply_failed("Unable to run command while another command is being processed");
+        return;
+    }
+
+    let seq = crate::com::send_command(&mut socket, Command::ReadSensors).unwrap();
+    *state = State::SentSensors { seq };
+}
+
+/// Send the get or set config command to the robot
+fn do_config(
+    mut cli: ConsoleCommand<ConfigCli>,
+    mut socket: ResMut<Socket>,
+    mut state: ResMut<State>,
+    robot: Res<RobotInfo>,
+) {
+    let ConfigCli { key, value } = match cli.take() {
+        Some(Ok(cmd)) => cmd,
+        _ => return,
+    };
+
+    if !robot.capabilities.contains(Capabilities::CONFIG) {
+        cli.reply_failed("The robot does not support configuration");
+        return;
+    }
+
+    if !matches!(*state, State::Normal) {
+        cli.reply_failed("Unable to run command while another command is being processed");
+        return;
+    }
+
+    let mut config_key = ConfigKey::new();
+    if config_key.push_str(&key).is_err() {
+        cli.reply_failed(format!("The key can be at most {CONFIG_KEY_MAX} bytes long"));
+        return;
+    }
+
+    let cmd = match value {
+        Some(value) => Command::SetConfig { key: config_key, value },
+        None => Command::GetConfig { key: config_key },
+    };
+    let seq = crate::com::send_command(&mut socket, cmd).unwrap();
+    *state = State::SentConfig { seq };
+}
+
+/// Parse a single step of a sequence using the same syntax as the standalone command
+fn parse_step(words: &[&str]) -> Result<Step, String> {
+    let step = match words.first() {
+        Some(&"drive") => {
+            let DriveCli { distance, speed } =
+                DriveCli::try_parse_from(words).map_err(|err| err.to_string())?;
+            Step::Drive {
+                distance,
+                speed: speed.into(),
+            }
+        }
+        Some(&"turn") => {
+            let TurnCli { angle, speed } =
+                TurnCli::try_parse_from(words).map_err(|err| err.to_string())?;
+            Step::Turn {
+                angle,
+                speed: speed.into(),
+            }
+        }
+        Some(&"arc") => {
+            let ArcCli {
+                radius,
+                distance,
+                speed,
+            } = ArcCli::try_parse_from(words).map_err(|err| err.to_string())?;
+            Step::Arc {
+                radius,
+                distance,
+                speed: speed.into(),
+            }
+        }
+        Some(&"scan") => {
+            let ScanCli {
+                start,
+                end,
+                step,
+                sensor,
+            } = ScanCli::try_parse_from(words).map_err(|err| err.to_string())?;
+            Step::Scan {
+                start,
+                end,
+                step: step.map(NonZeroU8::get),
+                sensor: sensor.into(),
+            }
+        }
+        Some(other) => return Err(format!("{other} cannot be part of a sequence")),
+        None => return Err("Empty step in sequence".into()),
+    };
+    Ok(step)
+}
+
+/// Send the sequence command to the robot
+fn do_sequence(
+    mut cli: ConsoleCommand<SequenceCli>,
+    mut socket: ResMut<Socket>,
+    mut state: ResMut<State>,
+    robot: Res<RobotInfo>,
+) {
+    let SequenceCli { steps } = match cli.take() {
         Some(Ok(cmd)) => cmd,
         _ => return,
     };
 
+    if !robot.capabilities.contains(Capabilities::SEQUENCE) {
+        cli.reply_failed("The robot does not support sequences");
+        return;
+    }
+
     if !matches!(*state, State::Normal) {
         cli.reply_failed("Unable to run command while another command is being processed");
         return;
     }
 
-    crate::com::send_command(&mut socket, Command::Scan { start, end }).unwrap();
-    *state = State::SentScan { start, end };
+    let line = steps.join(" ");
+    let mut sequence = Steps::new();
+    for words in line.split(';') {
+        let words: Vec<_> = words.split_whitespace().collect();
+        let step = match parse_step(&words) {
+            Ok(step) => step,
+            Err(err) => {
+                cli.reply_failed(err);
+                return;
+            }
+        };
+        if sequence.push(step).is_err() {
+            cli.reply_failed(format!("A sequence has at most {SEQUENCE_MAX} steps"));
+            return;
+        }
+    }
+
+    let seq = crate::com::send_command(&mut socket, Command::Sequence(sequence)).unwrap();
+    *state = State::SentSequence { seq, progress: 0. };
+}
+
+/// Send the stop command to the robot, preempting the running command
+fn send_stop(socket: &mut Socket, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let interrupted = match *state {
+        State::SentStop { interrupted, .. } => interrupted,
+        State::SentDrive { seq, .. }
+        | State::SentTurn { seq, .. }
+        | State::SentArc { seq, .. }
+        | State::SentScan { seq, .. }
+        | State::SentSweep { seq, .. }
+        | State::SentSequence { seq, .. } => Some(seq),
+        _ => None,
+    };
+    let seq = crate::com::send_command(socket, Command::Stop)?;
+    *state = State::SentStop {
+        seq,
+        interrupted,
+        progress: state.progress(),
+    };
+    Ok(())
+}
+
+/// Send the stop command to the robot from the console
+fn do_stop(
+    mut cli: ConsoleCommand<StopCli>,
+    mut socket: ResMut<Socket>,
+    mut state: ResMut<State>,
+    robot: Res<RobotInfo>,
+) {
+    let StopCli = match cli.take() {
+        Some(Ok(cmd)) => cmd,
+        _ => return,
+    };
+
+    if !robot.capabilities.contains(Capabilities::STOP) {
+        cli.reply_failed("The robot does not support stopping");
+        return;
+    }
+
+    if let Err(err) = send_stop(&mut socket, &mut state) {
+        cli.reply_failed(format!("Could not send the stop: {err}"));
+    }
+}
+
+/// Send the stop command to the robot when escape is pressed
+fn stop_hotkey(
+    keys: Res<Input<KeyCode>>,
+    mut socket: ResMut<Socket>,
+    mut state: ResMut<State>,
+    robot: Res<RobotInfo>,
+    mut console: EventWriter<PrintConsoleLine>,
+) {
+    if keys.just_pressed(KeyCode::Escape) && robot.capabilities.contains(Capabilities::STOP) {
+        if let Err(err) = send_stop(&mut socket, &mut state) {
+            console.send(PrintConsoleLine::new(format!("Could not send the stop: {err}").into()));
+        }
+    }
 }
 
 /// The plugin for adding the commands to the GUI
@@ -124,7 +542,14 @@ impl Plugin for CliPlugin {
         app.add_plugin(ConsolePlugin)
             .add_console_command::<DriveCli, _>(do_drive)
             .add_console_command::<TurnCli, _>(do_turn)
+            .add_console_command::<ArcCli, _>(do_arc)
             .add_console_command::<ScanCli, _>(do_scan)
+            .add_console_command::<SweepCli, _>(do_sweep)
+            .add_console_command::<SensorsCli, _>(do_sensors)
+            .add_console_command::<SequenceCli, _>(do_sequence)
+            .add_console_command::<ConfigCli, _>(do_config)
+            .add_console_command::<StopCli, _>(do_stop)
+            .add_system(stop_hotkey)
             .insert_resource(ConsoleConfiguration {
                 left_pos: 0.,
                 top_pos: 0.,
@@ -1,4 +1,4 @@
-use std::net::TcpStream;
+use std::{net::TcpStream, time::{Duration, Instant}};
 
 use bevy::{
     core_pipeline::clear_color::ClearColorConfig,
@@ -9,24 +9,170 @@ use bevy::{
     sprite::MaterialMesh2dBundle, input::mouse::MouseMotion,
 };
 use bevy_console::PrintConsoleLine;
-use com::read_response;
+use com::{read_response, ChunkAssembler, RobotLink, Stream};
 use console::CliPlugin;
-use cyproto_core::{ObjectData, Response};
+use status::{LinkStatus, RobotLog, RobotStatus, StatusPlugin};
+use teleop::TeleopPlugin;
+use cyproto_core::{
+    codec::CodecError,
+    transport::{Link, StdIo},
+    Capabilities, CliffSensor, Command, DriveTermination, Frame, ObjectData, Pose,
+    Response, Sensors, Seq, SweepData, NO_SEQ, PROTOCOL_VERSION,
+};
 
 mod com;
 mod console;
+mod status;
+mod teleop;
 
 const CYBOT_RADIUS_CM: f32 = 16.;
 
+/// How long the robot may take to acknowledge a command before it counts as lost
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The names of the optional features printed after the handshake
+const CAPABILITY_NAMES: [(Capabilities, &str); 13] = [
+    (Capabilities::SCAN, "scan"),
+    (Capabilities::STOP, "stop"),
+    (Capabilities::SWEEP, "sweep"),
+    (Capabilities::TELEMETRY, "telemetry"),
+    (Capabilities::ARC, "arc"),
+    (Capabilities::TELEOP, "teleop"),
+    (Capabilities::PING, "ping"),
+    (Capabilities::SENSORS, "sensors"),
+    (Capabilities::BOUNDARY, "boundary"),
+    (Capabilities::SEQUENCE, "sequence"),
+    (Capabilities::CONFIG, "config"),
+    (Capabilities::LOG, "log"),
+    (Capabilities::ACK, "ack"),
+];
+
 #[derive(Resource)]
-pub struct Socket(TcpStream);
+pub struct Socket {
+    link: RobotLink,
+    seq: Seq,
+    closed: bool,
+    /// Whether the robot said in the handshake that it acknowledges commands
+    acks: bool,
+    /// The last command sent that the robot has not acknowledged yet and when it was sent
+    unacked: Option<(Seq, Instant)>,
+}
+
+impl Socket {
+    pub fn new(stream: impl Stream + 'static) -> Self {
+        Self {
+            link: Link::new(StdIo(Box::new(stream))),
+            seq: 0,
+            closed: false,
+            acks: false,
+            unacked: None,
+        }
+    }
+
+    /// Record that the robot acknowledged or refused the command with the given sequence number
+    pub fn acknowledged(&mut self, seq: Seq) {
+        if matches!(self.unacked, Some((unacked, _)) if unacked == seq) {
+            self.unacked = None;
+        }
+    }
+
+    /// The sequence number of the last command if the robot took too long to acknowledge it
+    pub fn lost(&self) -> Option<Seq> {
+        self.unacked
+            .filter(|(_, sent)| sent.elapsed() > ACK_TIMEOUT)
+            .map(|(seq, _)| seq)
+    }
+
+    /// Get the sequence number to use for the next command
+    pub fn next_seq(&mut self) -> Seq {
+        self.seq = self.seq.wrapping_add(1);
+        if self.seq == NO_SEQ {
+            self.seq = self.seq.wrapping_add(1);
+        }
+        self.seq
+    }
+}
+
+/// What the robot told us about itself during the handshake
+#[derive(Clone, Copy, Debug, Default, Resource)]
+pub struct RobotInfo {
+    pub version: u16,
+    pub capabilities: Capabilities,
+    pub bytes_max: u16,
+    pub scan_max: u16,
+}
 
 #[derive(Clone, Copy, Debug, Resource, PartialEq)]
 pub enum State {
     Normal,
-    SentDrive { distance: f32 },
-    SentTurn { angle: f32 },
-    SentScan { start: u8, end: u8 },
+    SentHello { seq: Seq },
+    /// `progress` is how far the robot has reported getting so far
+    SentDrive { seq: Seq, distance: f32, progress: f32 },
+    SentTurn { seq: Seq, angle: f32, progress: f32 },
+    SentArc { seq: Seq, radius: f32, distance: f32 },
+    SentScan { seq: Seq, start: u8, end: u8 },
+    SentSweep { seq: Seq, start: u8, end: u8, step: u8 },
+    SentSensors { seq: Seq },
+    /// A get or set config was sent, both are answered with the current value
+    SentConfig { seq: Seq },
+    /// A sequence was sent, `progress` is for the drive or turn step currently running
+    SentSequence { seq: Seq, progress: f32 },
+    /// A stop was sent, it is answered by the result of whatever it interrupted
+    /// `interrupted` is the command it was sent to stop, which may finish before the stop arrives
+    /// `progress` is how far the interrupted drive or turn got before the stop was sent
+    SentStop { seq: Seq, interrupted: Option<Seq>, progress: f32 },
+}
+
+impl State {
+    /// The sequence number of the command we are waiting on, if any
+    pub fn seq(&self) -> Option<Seq> {
+        match self {
+            State::Normal => None,
+            State::SentHello { seq }
+            | State::SentDrive { seq, .. }
+            | State::SentTurn { seq, .. }
+            | State::SentArc { seq, .. }
+            | State::SentScan { seq, .. }
+            | State::SentSweep { seq, .. }
+            | State::SentSensors { seq }
+            | State::SentConfig { seq }
+            | State::SentSequence { seq, .. }
+            | State::SentStop { seq, .. } => Some(*seq),
+        }
+    }
+
+    /// Whether a response with this sequence number belongs to the command we are waiting on
+    pub fn expects(&self, seq: Seq) -> bool {
+        match *self {
+            State::SentStop {
+                interrupted: Some(interrupted),
+                ..
+            } if interrupted == seq => true,
+            _ => self.seq() == Some(seq),
+        }
+    }
+
+    /// How far the running drive or turn has reported getting so far
+    pub fn progress(&self) -> f32 {
+        match self {
+            State::SentDrive { progress, .. }
+            | State::SentTurn { progress, .. }
+            | State::SentSequence { progress, .. }
+            | State::SentStop { progress, .. } => *progress,
+            _ => 0.,
+        }
+    }
+
+    /// Record how far the running drive or turn has reported getting
+    pub fn set_progress(&mut self, value: f32) {
+        match self {
+            State::SentDrive { progress, .. }
+            | State::SentTurn { progress, .. }
+            | State::SentSequence { progress, .. }
+            | State::SentStop { progress, .. } => *progress = value,
+            _ => {}
+        }
+    }
 }
 
 #[derive(Component)]
@@ -38,47 +184,127 @@ pub struct PreviousCybot;
 #[derive(Component)]
 pub struct Object;
 
+/// Where the robot's own odometry says it is
+#[derive(Component)]
+pub struct ReportedCybot;
+
 #[derive(Component)]
 pub struct Obstacle;
 
+#[derive(Component)]
+pub struct SweepPoint;
+
+/// A stretch of the white tape at the edge of the field
+#[derive(Component)]
+pub struct Boundary;
+
+/// The kinds of things the sensors around the edge of the robot can run into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    Bump,
+    Cliff,
+    Boundary,
+}
+
+/// An obstacle found by one of the sensors around the edge of the robot
 #[derive(Clone, Copy)]
 pub struct CliffEvent {
-    color: Color,
+    kind: EdgeKind,
+    /// Where the sensor sits on the robot in degrees counter-clockwise from the front
+    angle: f32,
+    /// How much of the robot's edge the sensor covers in cm
+    width: f32,
+}
+
+impl CliffEvent {
+    /// The marker for the obstacle that ended a drive, if any
+    pub fn from_termination(termination: DriveTermination) -> Option<Self> {
+        use DriveTermination::*;
+
+        if let Some(sensor) = termination.boundary() {
+            return Some(Self::boundary(sensor));
+        }
+        let (kind, angle, width) = match (termination, termination.cliff()) {
+            (_, Some(sensor)) => (EdgeKind::Cliff, cliff_angle(sensor), CYBOT_RADIUS_CM / 2.),
+            (BumpLeft, _) => (EdgeKind::Bump, 35., CYBOT_RADIUS_CM),
+            (BumpRight, _) => (EdgeKind::Bump, -35., CYBOT_RADIUS_CM),
+            (BumpBoth, _) => (EdgeKind::Bump, 0., CYBOT_RADIUS_CM * 2.),
+            _ => return None,
+        };
+        Some(Self { kind, angle, width })
+    }
+
+    /// The marker for a stretch of the field's edge seen by a cliff sensor
+    pub fn boundary(sensor: CliffSensor) -> Self {
+        Self {
+            kind: EdgeKind::Boundary,
+            angle: cliff_angle(sensor),
+            width: CYBOT_RADIUS_CM,
+        }
+    }
+}
+
+/// Where a cliff sensor sits on the robot in degrees counter-clockwise from the front
+fn cliff_angle(sensor: CliffSensor) -> f32 {
+    match sensor {
+        CliffSensor::FrontLeft => 15.,
+        CliffSensor::Left => 65.,
+        CliffSensor::Right => -65.,
+        CliffSensor::FrontRight => -15.,
+    }
 }
 
+/// A piece of the path the robot followed
+///
+/// Each event is drawn as a straight line, arcs are sent as a chain of these along their chords.
 #[derive(Clone, Copy)]
-pub struct PathEvent;
+pub struct PathEvent {
+    from: Transform,
+    to: Transform,
+}
 
 fn cm_to_unit(cm: f32) -> f32 {
     cm * 2.0
 }
 
-/*fn unit_to_cm(unit: f32) -> f32 {
+fn unit_to_cm(unit: f32) -> f32 {
     unit / 2.0
-}*/
+}
+
+/// Place a pose reported by the robot on the field
+///
+/// Both start at the origin facing up so the pose maps directly onto the field.
+pub fn pose_to_transform(pose: Pose) -> Transform {
+    Transform::from_xyz(cm_to_unit(pose.x), cm_to_unit(pose.y), 0.)
+        .with_rotation(Quat::from_rotation_z(pose.heading.to_radians()))
+}
+
+/// Get the pose of something on the field in the robot's terms
+pub fn transform_to_pose(transform: &Transform) -> Pose {
+    let (heading, _, _) = transform.rotation.to_euler(EulerRot::ZYX);
+    Pose {
+        x: unit_to_cm(transform.translation.x),
+        y: unit_to_cm(transform.translation.y),
+        heading: heading.to_degrees().rem_euclid(360.),
+    }
+}
 
 /// Spawn the path that the robot followed
 fn spawn_path(
     mut ev_path: EventReader<PathEvent>,
-    cybot_pos: Query<&Transform, (With<Cybot>, Without<PreviousCybot>)>,
-    prev_pos: Query<&Transform, (With<PreviousCybot>, Without<Cybot>)>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    let prev_pos = prev_pos.single();
-    let cybot_pos = cybot_pos.single();
-
     const LINE_WIDTH: f32 = 2.;
-    let line_height = (prev_pos.translation - cybot_pos.translation)
-        .length()
-        .abs();
-    let mut mid: Transform =
-        Transform::from_translation(prev_pos.translation.lerp(cybot_pos.translation, 0.5));
-    mid.translation.z = 0.;
-    mid.rotate(cybot_pos.rotation);
-
-    for _ in ev_path.iter() {
+
+    for PathEvent { from, to } in ev_path.iter() {
+        let line_height = (from.translation - to.translation).length().abs();
+        let mut mid: Transform =
+            Transform::from_translation(from.translation.lerp(to.translation, 0.5));
+        mid.translation.z = 0.;
+        mid.rotate(to.rotation);
+
         commands.spawn(
             MaterialMesh2dBundle {
                 mesh: meshes
@@ -102,29 +328,39 @@ fn spawn_cliff(
 ) {
     let cybot_pos = cybot_pos.single();
     for ev in ev_cliffs.iter() {
-        let mut obj_pos = cybot_pos.clone();
+        // put the marker on the edge of the robot in front of the sensor
+        let mut obj_pos = *cybot_pos;
+        obj_pos.rotate_z(ev.angle.to_radians());
         obj_pos.translation +=
-            cybot_pos
+            obj_pos
                 .rotation
-                .mul_vec3(Vec3::new(0., cm_to_unit(CYBOT_RADIUS_CM / 2.), 0.));
+                .mul_vec3(Vec3::new(0., cm_to_unit(CYBOT_RADIUS_CM), 0.));
         obj_pos.translation.z += 2.;
-        commands.spawn((
+        let color = match ev.kind {
+            EdgeKind::Bump => Color::ORANGE,
+            EdgeKind::Cliff => Color::RED,
+            EdgeKind::Boundary => Color::WHITE,
+        };
+        let mut marker = commands.spawn(
             MaterialMesh2dBundle {
                 mesh: meshes
                     .add(
                         Quad::new(Vec2::new(
-                            cm_to_unit(CYBOT_RADIUS_CM * 2.),
+                            cm_to_unit(ev.width),
                             cm_to_unit(CYBOT_RADIUS_CM / 5.),
                         ))
                         .into(),
                     )
                     .into(),
-                material: materials.add(ColorMaterial::from(ev.color)),
+                material: materials.add(ColorMaterial::from(color)),
                 transform: obj_pos,
                 ..default()
             },
-            Obstacle,
-        ));
+        );
+        match ev.kind {
+            EdgeKind::Boundary => marker.insert(Boundary),
+            EdgeKind::Bump | EdgeKind::Cliff => marker.insert(Obstacle),
+        };
     }
 }
 
@@ -138,7 +374,7 @@ fn spawn_object(
 ) {
     let cybot_pos = cybot_pos.single();
     let scanner_pos = {
-        let mut sp = cybot_pos.clone();
+        let mut sp = *cybot_pos;
         sp.translation += sp
             .rotation
             .mul_vec3(Vec3::new(0., cm_to_unit(CYBOT_RADIUS_CM - 2.), 0.));
@@ -146,7 +382,7 @@ fn spawn_object(
     };
     for object in ev_objs.iter() {
         let obj_radius = object.width / 2.;
-        let mut obj_pos = cybot_pos.clone();
+        let mut obj_pos = *cybot_pos;
         obj_pos.translation += obj_pos.rotation.mul_vec3(Vec3::new(
             cm_to_unit(object.distance + obj_radius),
             cm_to_unit(CYBOT_RADIUS_CM - 2.),
@@ -182,6 +418,61 @@ fn spawn_object(
     }
 }
 
+/// Plot the raw readings of a sweep around the scanner, IR in cyan and ping in yellow
+fn spawn_sweep(
+    mut ev_sweep: EventReader<SweepData>,
+    cybot_pos: Query<&Transform, (With<Cybot>, Without<PreviousCybot>)>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    const POINT_RADIUS_CM: f32 = 0.75;
+
+    let cybot_pos = cybot_pos.single();
+    let scanner_pos = {
+        let mut sp = *cybot_pos;
+        sp.translation += sp
+            .rotation
+            .mul_vec3(Vec3::new(0., cm_to_unit(CYBOT_RADIUS_CM - 2.), 0.));
+        sp.translation
+    };
+    let mesh = meshes.add(Circle::new(cm_to_unit(POINT_RADIUS_CM)).into());
+    let ir_material = materials.add(ColorMaterial::from(Color::CYAN));
+    let ping_material = materials.add(ColorMaterial::from(Color::YELLOW));
+
+    for reading in ev_sweep.iter() {
+        let readings = [
+            (reading.ir_distance, ir_material.clone()),
+            (reading.ping_distance, ping_material.clone()),
+        ];
+        for (distance, material) in readings {
+            if !distance.is_finite() || distance <= 0. {
+                continue;
+            }
+            let mut point_pos = *cybot_pos;
+            point_pos.translation += point_pos.rotation.mul_vec3(Vec3::new(
+                cm_to_unit(distance),
+                cm_to_unit(CYBOT_RADIUS_CM - 2.),
+                0.,
+            ));
+            point_pos.rotate_around(
+                scanner_pos,
+                Quat::from_rotation_z(f32::from(reading.angle).to_radians()),
+            );
+
+            commands.spawn((
+                MaterialMesh2dBundle {
+                    mesh: mesh.clone().into(),
+                    material,
+                    transform: point_pos,
+                    ..default()
+                },
+                SweepPoint,
+            ));
+        }
+    }
+}
+
 /// Initial setup of the data required for the GUI
 fn setup(
     mut commands: Commands,
@@ -191,7 +482,6 @@ fn setup(
     commands.spawn(Camera2dBundle {
         camera_2d: Camera2d {
             clear_color: ClearColorConfig::Custom(Color::BLACK),
-            ..default()
         },
         ..default()
     });
@@ -230,6 +520,52 @@ fn setup(
         Transform::from_translation(Vec3::new(0., 0., 1.)),
         PreviousCybot,
     ));
+
+    // a see-through copy of the robot drawn wherever the robot thinks it is
+    let ghost = Color::rgba(0.3, 0.6, 1., 0.4);
+    commands
+        .spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes
+                    .add(Circle::new(cm_to_unit(CYBOT_RADIUS_CM)).into())
+                    .into(),
+                material: materials.add(ColorMaterial::from(ghost)),
+                transform: Transform::from_translation(Vec3::new(0., 0., 0.5)),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            ReportedCybot,
+        ))
+        .with_children(|parent| {
+            parent.spawn(MaterialMesh2dBundle {
+                mesh: meshes
+                    .add(
+                        Quad::new(Vec2::new(
+                            cm_to_unit(CYBOT_RADIUS_CM / 5.),
+                            cm_to_unit(CYBOT_RADIUS_CM),
+                        ))
+                        .into(),
+                    )
+                    .into(),
+                material: materials.add(ColorMaterial::from(ghost)),
+                transform: Transform::from_translation(Vec3::new(0., CYBOT_RADIUS_CM, 0.1)),
+                ..default()
+            });
+        });
+}
+
+/// Move the copy of the robot to wherever the robot last said it is
+fn place_reported_cybot(
+    status: Res<RobotStatus>,
+    mut reported: Query<(&mut Transform, &mut Visibility), With<ReportedCybot>>,
+) {
+    let Some(pose) = status.pose else {
+        return;
+    };
+    let (mut transform, mut visibility) = reported.single_mut();
+    *transform = pose_to_transform(pose);
+    transform.translation.z = 0.5;
+    *visibility = Visibility::Visible;
 }
 
 
@@ -248,116 +584,576 @@ fn cursor_drag(
     }
 }
 
+/// Move the robot forward by the given distance and draw the path it took
+fn drive_by(
+    distance: f32,
+    cybot_pos: &mut Transform,
+    prev_pos: &mut Transform,
+    ev_path: &mut EventWriter<PathEvent>,
+) {
+    *prev_pos = *cybot_pos;
+    let move_by = cybot_pos
+        .rotation
+        .mul_vec3(Vec3::new(0., cm_to_unit(distance), 0.));
+    cybot_pos.translation += move_by;
+    if distance == 0. {
+        return;
+    }
+    ev_path.send(PathEvent {
+        from: *prev_pos,
+        to: *cybot_pos,
+    });
+}
+
+/// Turn the robot counter-clockwise by the given angle in degrees
+fn turn_by(angle: f32, cybot_pos: &mut Transform, prev_pos: &mut Transform) {
+    *prev_pos = *cybot_pos;
+    cybot_pos.rotate_z(angle.to_radians());
+}
+
+/// Move the robot along an arc and draw it as a series of short straight segments
+///
+/// `angle` is how far the robot turned while driving `distance`, each segment is a chord of
+/// the arc so the ends of the segments lie exactly on the circle the robot followed.
+fn arc_by(
+    distance: f32,
+    angle: f32,
+    cybot_pos: &mut Transform,
+    prev_pos: &mut Transform,
+    ev_path: &mut EventWriter<PathEvent>,
+) {
+    const DEGREES_PER_SEGMENT: f32 = 5.;
+
+    let segments = (angle.abs() / DEGREES_PER_SEGMENT).ceil().max(1.);
+    let segment_angle = angle / segments;
+    let segment_distance = distance / segments;
+    let chord = if segment_angle == 0. {
+        segment_distance
+    } else {
+        let radians = segment_angle.to_radians();
+        2. * (segment_distance / radians) * (radians / 2.).sin()
+    };
+
+    for _ in 0..segments as usize {
+        turn_by(segment_angle / 2., cybot_pos, prev_pos);
+        drive_by(chord, cybot_pos, prev_pos, ev_path);
+        turn_by(segment_angle / 2., cybot_pos, prev_pos);
+    }
+}
+
+/// Print a snapshot of the robot's sensors
+fn report_sensors(sensors: &Sensors, console: &mut EventWriter<PrintConsoleLine>) {
+    let Sensors {
+        bump_left,
+        bump_right,
+        cliff: [front_left, left, right, front_right],
+        servo_angle,
+        ir_distance,
+        ping_distance,
+        battery_voltage,
+    } = *sensors;
+
+    console.send_batch([
+        PrintConsoleLine::new("Sensors:".into()),
+        PrintConsoleLine::new(format!("\tbump: left {bump_left} right {bump_right}").into()),
+        PrintConsoleLine::new(
+            format!("\tcliff: {front_left} {left} {right} {front_right}").into(),
+        ),
+        PrintConsoleLine::new(
+            format!("\tat {servo_angle}°: ir {ir_distance:.2}cm ping {ping_distance:.2}cm").into(),
+        ),
+        PrintConsoleLine::new(format!("\tbattery: {battery_voltage:.2}V").into()),
+    ]);
+}
+
+/// Print the objects found by a scan and add them to the field
+fn report_scan(
+    data: &[ObjectData],
+    console: &mut EventWriter<PrintConsoleLine>,
+    ev_objs: &mut EventWriter<ObjectData>,
+) {
+    console.send(PrintConsoleLine::new(
+        format!("Scanned: {} objects", data.len()).into(),
+    ));
+
+    console.send_batch(data.iter().enumerate().map(|(i, obj)| {
+        PrintConsoleLine::new(
+            format!(
+                "\t{i}. angle: {} distance: {:.2} width: {:.2}",
+                obj.angle, obj.distance, obj.width
+            )
+            .into(),
+        )
+    }));
+
+    ev_objs.send_batch(data.iter().copied());
+}
+
+/// Print a summary of a sweep and plot its readings on the field
+fn report_sweep(
+    data: &[SweepData],
+    console: &mut EventWriter<PrintConsoleLine>,
+    ev_sweep: &mut EventWriter<SweepData>,
+) {
+    console.send(PrintConsoleLine::new(
+        format!("Swept: {} readings", data.len()).into(),
+    ));
+    ev_sweep.send_batch(data.iter().copied());
+}
+
 /// Update the state of the GUI checking if a command was sent to the robot, and a response has
 /// come back
+#[allow(clippy::too_many_arguments)]
 fn update(
     mut ev_objs: EventWriter<ObjectData>,
     mut ev_cliffs: EventWriter<CliffEvent>,
     mut ev_path: EventWriter<PathEvent>,
+    mut ev_sweep: EventWriter<SweepData>,
     mut console: EventWriter<PrintConsoleLine>,
     mut state: ResMut<State>,
     mut socket: ResMut<Socket>,
+    mut robot: ResMut<RobotInfo>,
+    mut scan: ResMut<ChunkAssembler<ObjectData>>,
+    mut sweep: ResMut<ChunkAssembler<SweepData>>,
+    mut status: ResMut<RobotStatus>,
+    mut link: ResMut<LinkStatus>,
+    mut log: ResMut<RobotLog>,
+    time: Res<Time>,
     mut cybot: Query<&mut Transform, (With<Cybot>, Without<PreviousCybot>)>,
     mut prev: Query<&mut Transform, (With<PreviousCybot>, Without<Cybot>)>,
 ) {
     let mut cybot_pos = cybot.single_mut();
     let mut prev_pos = prev.single_mut();
 
-    // If the state is non-normal then we sent a command, check for a response
-    if *state != State::Normal {
-        let response = read_response(&mut socket);
-        if response.is_err() {
-            console.send(PrintConsoleLine::new(response.unwrap_err().to_string().into()));
-            *state = State::Normal;
+    // The robot can send telemetry at any time so always check for a response
+    let response = match read_response(&mut socket) {
+        Ok(response) => response,
+        // a corrupted frame is dropped, the command it belonged to can still be stopped
+        Err(err) if err.is::<CodecError>() && !matches!(*state, State::SentHello { .. }) => {
+            console.send(PrintConsoleLine::new(format!("Dropped a frame: {err}").into()));
             return;
         }
-        let response = response.unwrap();
-        match (*state, response) {
-            (
-                State::SentDrive { .. },
-                Some(Response::DriveDone {
-                    total_distance,
-                    cliff_detected,
-                    bump_detected,
-                }),
-            ) => {
-                *prev_pos = cybot_pos.clone();
-                let move_by =
-                    cybot_pos
-                        .rotation
-                        .mul_vec3(Vec3::new(0., cm_to_unit(total_distance), 0.));
-                cybot_pos.translation += move_by;
-
-                console.send_batch([
-                    PrintConsoleLine::new(format!("Drove: {total_distance:.2}cm").into()),
-                    PrintConsoleLine::new(format!("\tcliff: {cliff_detected}").into()),
-                    PrintConsoleLine::new(format!("\tbump: {bump_detected}").into()),
-                ]);
-                ev_path.send(PathEvent);
-
-                if cliff_detected {
-                    ev_cliffs.send(CliffEvent { color: Color::RED });
-                }
-                if bump_detected {
-                    ev_cliffs.send(CliffEvent { color: Color::ORANGE });
-                }
-            }
-            (State::SentTurn { .. }, Some(Response::TurnDone { total_angle })) => {
-                *prev_pos = cybot_pos.clone();
-                cybot_pos.rotate_z(total_angle.to_radians());
-                console.send(PrintConsoleLine::new(format!("Turned: {total_angle:.2}°").into()));
+        Err(err) => {
+            console.send(PrintConsoleLine::new(err.to_string().into()));
+            if let State::SentHello { .. } = *state {
+                console.send(PrintConsoleLine::new(
+                    format!("Handshake failed, the firmware does not speak v{PROTOCOL_VERSION}").into(),
+                ));
             }
-            (State::SentScan { .. }, Some(Response::ScanDone { data })) => {
+            *state = State::Normal;
+            return;
+        }
+    };
+    if let Some(Frame {
+        seq,
+        msg: Response::Ack | Response::Nack { .. },
+    }) = response
+    {
+        socket.acknowledged(seq);
+    }
+    let frame_seq = response.as_ref().map_or(NO_SEQ, |frame| frame.seq);
+    let response = match response {
+        // the result of the command follows later
+        Some(Frame {
+            msg: Response::Ack,
+            ..
+        }) => return,
+        // telemetry is not tied to any command
+        Some(Frame {
+            msg: Response::Telemetry(telemetry),
+            ..
+        }) => {
+            status.telemetry = Some(telemetry);
+            status.pose = Some(telemetry.pose);
+            status.received = time.elapsed_seconds_f64();
+            return;
+        }
+        // the edge of the field can be crossed at any time
+        Some(Frame {
+            msg: Response::Boundary { sensor, signal },
+            ..
+        }) => {
+            console.send(PrintConsoleLine::new(
+                format!("Boundary: {sensor:?} cliff sensor read {signal}").into(),
+            ));
+            ev_cliffs.send(CliffEvent::boundary(sensor));
+            return;
+        }
+        // pings are answered alongside whatever command is running
+        Some(Frame {
+            msg: Response::Pong { nonce },
+            ..
+        }) => {
+            link.pong(nonce, time.elapsed_seconds_f64());
+            return;
+        }
+        // the console can only show plain text so the level is spelled out there
+        // and the log window shows it in colour
+        Some(Frame {
+            msg: Response::Log { level, message },
+            ..
+        }) => {
+            console.send(PrintConsoleLine::new(format!("[{level:?}] {message}").into()));
+            log.push(level, message.to_string());
+            return;
+        }
+        Some(Frame { seq, msg }) if state.expects(seq) => Some(msg),
+        // progress of a command that was stopped, its final result comes with the stop
+        Some(Frame {
+            msg: Response::DriveProgress { .. } | Response::TurnProgress { .. },
+            ..
+        }) => return,
+        // the robot could not tell which command failed so it must be the pending one
+        Some(Frame {
+            seq: NO_SEQ,
+            msg: msg @ (Response::Error { .. } | Response::Nack { .. }),
+        }) => Some(msg),
+        Some(Frame { seq, msg }) => {
+            // a late or duplicated response to some earlier command
+            console.send(PrintConsoleLine::new(format!("Ignoring response {seq}: {msg:?}").into()));
+            return;
+        }
+        None => None,
+    };
+    // the results of the steps of a sequence keep coming until it is done or fails
+    let in_sequence = matches!(*state, State::SentSequence { .. })
+        && !matches!(
+            response,
+            None | Some(Response::SequenceDone { .. } | Response::Error { .. } | Response::Nack { .. })
+        );
+    match (*state, response) {
+        (
+            State::SentHello { .. },
+            Some(Response::Hello {
+                version,
+                capabilities,
+                bytes_max,
+                scan_max,
+            }),
+        ) => {
+            *robot = RobotInfo {
+                version,
+                capabilities,
+                bytes_max,
+                scan_max,
+            };
+            socket.acks = capabilities.contains(Capabilities::ACK);
+            if version == PROTOCOL_VERSION {
+                console.send(PrintConsoleLine::new(
+                    format!("Connected to firmware speaking v{version}").into(),
+                ));
+            } else {
                 console.send(PrintConsoleLine::new(
-                    format!("Scanned: {} objects", data.len()).into(),
+                    format!("Firmware speaks v{version}, GUI speaks v{PROTOCOL_VERSION}").into(),
                 ));
+            }
+            console.send(PrintConsoleLine::new(
+                format!("\tbuffer: {bytes_max} bytes, scan: {scan_max} objects").into(),
+            ));
+            let supported: Vec<_> = CAPABILITY_NAMES
+                .iter()
+                .filter(|(capability, _)| capabilities.contains(*capability))
+                .map(|(_, name)| *name)
+                .collect();
+            console.send(PrintConsoleLine::new(
+                format!("\tsupports: {}", supported.join(" ")).into(),
+            ));
+        }
+        (
+            State::SentDrive { progress, .. }
+            | State::SentSequence { progress, .. }
+            | State::SentStop { progress, .. },
+            Some(Response::DriveProgress { distance_so_far }),
+        ) => {
+            drive_by(distance_so_far - progress, &mut cybot_pos, &mut prev_pos, &mut ev_path);
+            state.set_progress(distance_so_far);
+            // keep waiting for the drive to finish
+            return;
+        }
+        (
+            State::SentTurn { progress, .. }
+            | State::SentSequence { progress, .. }
+            | State::SentStop { progress, .. },
+            Some(Response::TurnProgress { angle_so_far }),
+        ) => {
+            turn_by(angle_so_far - progress, &mut cybot_pos, &mut prev_pos);
+            state.set_progress(angle_so_far);
+            // keep waiting for the turn to finish
+            return;
+        }
+        (
+            State::SentDrive { progress, .. }
+            | State::SentSequence { progress, .. }
+            | State::SentStop { progress, .. },
+            Some(Response::DriveDone {
+                total_distance,
+                termination,
+                pose,
+            }),
+        ) => {
+            drive_by(total_distance - progress, &mut cybot_pos, &mut prev_pos, &mut ev_path);
+            status.pose = pose.or(status.pose);
 
-                console.send_batch(data.iter().enumerate().map(|(i, obj)| {
-                    PrintConsoleLine::new(
-                        format!(
-                            "\t{i}. angle: {} distance: {:.2} width: {:.2}",
-                            obj.angle, obj.distance, obj.width
-                        )
-                        .into(),
-                    )
-                }));
+            console.send_batch([
+                PrintConsoleLine::new(format!("Drove: {total_distance:.2}cm").into()),
+                PrintConsoleLine::new(format!("\tended: {termination:?}").into()),
+            ]);
 
-                ev_objs.send_batch(data);
+            if let Some(ev) = CliffEvent::from_termination(termination) {
+                ev_cliffs.send(ev);
+            }
+        }
+        (
+            State::SentTurn { progress, .. }
+            | State::SentSequence { progress, .. }
+            | State::SentStop { progress, .. },
+            Some(Response::TurnDone { total_angle, pose }),
+        ) => {
+            turn_by(total_angle - progress, &mut cybot_pos, &mut prev_pos);
+            status.pose = pose.or(status.pose);
+            console.send(PrintConsoleLine::new(format!("Turned: {total_angle:.2}°").into()));
+        }
+        (
+            State::SentArc { .. } | State::SentSequence { .. } | State::SentStop { .. },
+            Some(Response::ArcDone {
+                total_distance,
+                total_angle,
+                pose,
+            }),
+        ) => {
+            arc_by(total_distance, total_angle, &mut cybot_pos, &mut prev_pos, &mut ev_path);
+            status.pose = pose.or(status.pose);
+            console.send(PrintConsoleLine::new(
+                format!("Arc: {total_distance:.2}cm turning {total_angle:.2}°").into(),
+            ));
+        }
+        (
+            State::SentScan { .. } | State::SentSequence { .. } | State::SentStop { .. },
+            Some(Response::ScanDone { data }),
+        ) => {
+            report_scan(&data, &mut console, &mut ev_objs);
+        }
+        (
+            State::SentScan { .. } | State::SentSequence { .. } | State::SentStop { .. },
+            Some(Response::ScanChunk { index, total, data }),
+        ) => {
+            match scan.push(frame_seq, index, total, &data) {
+                // keep waiting for the rest of the scan
+                Ok(()) => return,
+                Err(err) => console.send(PrintConsoleLine::new(err.into())),
             }
-            (_, None) => {
-                return;
+        }
+        (
+            State::SentScan { .. } | State::SentSequence { .. } | State::SentStop { .. },
+            Some(Response::ScanEnd { count }),
+        ) => match scan.finish(frame_seq, count) {
+            Ok(data) => report_scan(&data, &mut console, &mut ev_objs),
+            Err(err) => console.send(PrintConsoleLine::new(err.into())),
+        },
+        (
+            State::SentSweep { .. } | State::SentStop { .. },
+            Some(Response::SweepChunk { index, total, data }),
+        ) => {
+            match sweep.push(frame_seq, index, total, &data) {
+                // keep waiting for the rest of the sweep
+                Ok(()) => return,
+                Err(err) => console.send(PrintConsoleLine::new(err.into())),
             }
-            (cmd, resp) => {
-                console.send(PrintConsoleLine::new(format!("Invalid response for command: {cmd:?} {resp:?}").into()));
-            },
         }
-        *state = State::Normal;
+        (
+            State::SentSweep { .. } | State::SentStop { .. },
+            Some(Response::SweepEnd { count }),
+        ) => match sweep.finish(frame_seq, count) {
+            Ok(data) => report_sweep(&data, &mut console, &mut ev_sweep),
+            Err(err) => console.send(PrintConsoleLine::new(err.into())),
+        },
+        (State::SentSensors { .. }, Some(Response::Sensors(sensors))) => {
+            report_sensors(&sensors, &mut console);
+        }
+        (State::SentConfig { .. }, Some(Response::Config { key, value })) => {
+            console.send(PrintConsoleLine::new(format!("Config: {key} = {value}").into()));
+        }
+        (
+            State::SentSequence { .. } | State::SentStop { .. },
+            Some(Response::SequenceDone { completed }),
+        ) => {
+            console.send(PrintConsoleLine::new(
+                format!("Sequence done: {completed} steps completed").into(),
+            ));
+        }
+        (State::SentStop { .. }, Some(Response::Stopped)) => {
+            console.send(PrintConsoleLine::new("Stopped: nothing was running".into()));
+        }
+        (_, Some(Response::Error { code, detail })) => {
+            console.send(PrintConsoleLine::new(
+                format!("Command rejected: {code:?} {detail}").into(),
+            ));
+        }
+        (_, Some(Response::Nack { reason })) => {
+            console.send(PrintConsoleLine::new(format!("Command refused: {reason:?}").into()));
+        }
+        // only give up on a command once every frame that arrived before it timed out was read
+        (_, None) => match socket.lost() {
+            Some(seq) if state.seq() == Some(seq) => {
+                socket.unacked = None;
+                console.send(PrintConsoleLine::new(
+                    format!("The robot never received command {seq}").into(),
+                ));
+            }
+            _ => return,
+        },
+        (cmd, resp) => {
+            console.send(PrintConsoleLine::new(format!("Invalid response for command: {cmd:?} {resp:?}").into()));
+        },
+    }
+    if in_sequence {
+        state.set_progress(0.);
+        return;
+    }
+    // the command finished before the stop reached the robot, which still answers the stop
+    if let State::SentStop {
+        seq,
+        interrupted: Some(interrupted),
+        ..
+    } = *state
+    {
+        if frame_seq == interrupted {
+            *state = State::SentStop {
+                seq,
+                interrupted: None,
+                progress: 0.,
+            };
+            return;
+        }
     }
+    *state = State::Normal;
 }
 
 /// The main function where the GUI is initialized
 fn main() {
     // create the connection to the cybot
-    let socket = Socket(TcpStream::connect("localhost:2888").unwrap());
-    socket
-        .0
+    let stream = TcpStream::connect("localhost:2888").unwrap();
+    stream
         .set_nonblocking(true)
         .expect("cannot get non-blocking");
+    let mut socket = Socket::new(stream);
+
+    // agree on a protocol version before anything else is sent
+    let seq = com::send_command(
+        &mut socket,
+        Command::Hello {
+            version: PROTOCOL_VERSION,
+        },
+    )
+    .unwrap();
 
     // Start the GUI
     App::new()
         .add_plugins(DefaultPlugins)
         .add_plugin(CliPlugin)
-        .insert_resource(State::Normal)
+        .add_plugin(StatusPlugin)
+        .add_plugin(TeleopPlugin)
+        .insert_resource(State::SentHello { seq })
+        .insert_resource(RobotInfo::default())
+        .init_resource::<ChunkAssembler<ObjectData>>()
+        .init_resource::<ChunkAssembler<SweepData>>()
         .insert_resource(socket)
         .add_event::<PathEvent>()
         .add_event::<ObjectData>()
         .add_event::<CliffEvent>()
+        .add_event::<SweepData>()
         .add_startup_system(setup)
         .add_system(spawn_path)
         .add_system(spawn_object)
         .add_system(spawn_cliff)
+        .add_system(spawn_sweep)
         .add_system(cursor_drag)
+        .add_system(place_reported_cybot)
         .add_system(update)
         .run();
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::{event::Events, system::SystemState};
+
+    use super::*;
+
+    /// Drive an arc from the origin facing up, returning the end pose and the segments drawn
+    fn run_arc(distance: f32, angle: f32) -> (Transform, Vec<PathEvent>) {
+        let mut world = World::new();
+        world.init_resource::<Events<PathEvent>>();
+        let mut system_state: SystemState<EventWriter<PathEvent>> = SystemState::new(&mut world);
+        let mut ev_path = system_state.get_mut(&mut world);
+        let mut cybot_pos = Transform::IDENTITY;
+        let mut prev_pos = Transform::IDENTITY;
+        arc_by(distance, angle, &mut cybot_pos, &mut prev_pos, &mut ev_path);
+
+        let events = world.resource::<Events<PathEvent>>();
+        let path = events.get_reader().iter(events).copied().collect();
+        (cybot_pos, path)
+    }
+
+    /// Where an arc from the origin facing up ends, the centre of its circle is to the left
+    fn closed_form(distance: f32, angle: f32) -> Vec3 {
+        let radians = angle.to_radians();
+        let radius = cm_to_unit(distance) / radians;
+        Vec3::new(radius * (radians.cos() - 1.), radius * radians.sin(), 0.)
+    }
+
+    fn heading(transform: &Transform) -> f32 {
+        transform.rotation.to_euler(EulerRot::XYZ).2.to_degrees()
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-3), "{a} is not {b}");
+    }
+
+    #[test]
+    fn arc_ends_on_the_closed_form_pose() {
+        let (end, _) = run_arc(30., 90.);
+        assert_close(end.translation, closed_form(30., 90.));
+        assert!((heading(&end) - 90.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn negative_angle_curves_right() {
+        let (end, _) = run_arc(30., -90.);
+        assert_close(end.translation, closed_form(30., -90.));
+        assert!(end.translation.x > 0.);
+        assert!((heading(&end) + 90.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn zero_angle_drives_straight() {
+        let (end, path) = run_arc(20., 0.);
+        assert_close(end.translation, Vec3::new(0., cm_to_unit(20.), 0.));
+        assert_eq!(path.len(), 1);
+    }
+
+    #[test]
+    fn zero_radius_turns_in_place() {
+        let (end, path) = run_arc(0., 45.);
+        assert_close(end.translation, Vec3::ZERO);
+        assert!((heading(&end) - 45.).abs() < 1e-3);
+        assert!(path.is_empty());
+    }
+
+    #[test]
+    fn chords_join_up_on_the_circle() {
+        let (distance, angle) = (40., 132.);
+        let (end, path) = run_arc(distance, angle);
+        assert_eq!(path.len(), 27);
+
+        let radius = cm_to_unit(distance) / angle.to_radians();
+        let centre = Vec3::new(-radius, 0., 0.);
+        assert_close(path[0].from.translation, Vec3::ZERO);
+        assert_close(path[path.len() - 1].to.translation, end.translation);
+        for pair in path.windows(2) {
+            assert_close(pair[0].to.translation, pair[1].from.translation);
+        }
+        for segment in &path {
+            assert!((segment.to.translation.distance(centre) - radius).abs() < 1e-3);
+        }
+    }
+}
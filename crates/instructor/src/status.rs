@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use cyproto_core::{Capabilities, Command, LogLevel, Pose, Telemetry};
+
+use crate::{Cybot, RobotInfo, Socket};
+
+/// How often the link is checked with a ping in seconds
+const PING_PERIOD: f64 = 1.;
+/// How long the robot may take to answer a ping before the link counts as down in seconds
+const LINK_TIMEOUT: f64 = 3.;
+/// How many of the firmware's log messages are kept for the log window
+const LOG_HISTORY: usize = 100;
+
+/// The latest state the robot reported on its own
+#[derive(Debug, Default, Resource)]
+pub struct RobotStatus {
+    pub telemetry: Option<Telemetry>,
+    /// When the telemetry was received in seconds since startup
+    pub received: f64,
+    /// The latest pose the robot's own odometry reported in telemetry or a motion response
+    pub pose: Option<Pose>,
+}
+
+/// The health of the link to the robot as measured by pings
+#[derive(Debug, Default, Resource)]
+pub struct LinkStatus {
+    /// The nonce of the last ping and when it was sent in seconds since startup
+    pub ping: Option<(u32, f64)>,
+    /// When the last matching pong arrived in seconds since startup
+    pub pong: Option<f64>,
+    /// The round trip time of the last answered ping in seconds
+    pub rtt: Option<f64>,
+    next_nonce: u32,
+}
+
+impl LinkStatus {
+    /// Record a pong, answers to anything but the latest ping are ignored
+    pub fn pong(&mut self, nonce: u32, now: f64) {
+        if let Some((sent_nonce, sent)) = self.ping {
+            if sent_nonce == nonce {
+                self.pong = Some(now);
+                self.rtt = Some(now - sent);
+            }
+        }
+    }
+
+    /// Whether the robot has answered a ping recently
+    pub fn up(&self, now: f64) -> bool {
+        matches!(self.pong, Some(pong) if now - pong < LINK_TIMEOUT)
+    }
+}
+
+/// The latest messages the firmware logged, oldest first
+#[derive(Debug, Default, Resource)]
+pub struct RobotLog {
+    pub messages: VecDeque<(LogLevel, String)>,
+}
+
+impl RobotLog {
+    /// Record a message, forgetting the oldest one once the history is full
+    pub fn push(&mut self, level: LogLevel, message: String) {
+        if self.messages.len() == LOG_HISTORY {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((level, message));
+    }
+}
+
+/// Periodically ping the robot to measure the link in the background
+fn heartbeat(
+    robot: Res<RobotInfo>,
+    time: Res<Time>,
+    mut socket: ResMut<Socket>,
+    mut link: ResMut<LinkStatus>,
+) {
+    if !robot.capabilities.contains(Capabilities::PING) {
+        return;
+    }
+
+    let now = time.elapsed_seconds_f64();
+    if matches!(link.ping, Some((_, sent)) if now - sent < PING_PERIOD) {
+        return;
+    }
+
+    let nonce = link.next_nonce;
+    link.next_nonce = nonce.wrapping_add(1);
+    // a failed send shows up as the link going down, the attempt still waits a period before
+    // the next one so a dead link is not retried every frame
+    let _ = crate::com::send_command(&mut socket, Command::Ping { nonce });
+    link.ping = Some((nonce, now));
+}
+
+fn format_pose(pose: Pose) -> String {
+    format!("x {:.1}cm y {:.1}cm {:.1}°", pose.x, pose.y, pose.heading)
+}
+
+/// Show the live status of the robot in a panel on the right of the window
+fn status_panel(
+    mut contexts: EguiContexts,
+    status: Res<RobotStatus>,
+    link: Res<LinkStatus>,
+    time: Res<Time>,
+    cybot: Query<&Transform, With<Cybot>>,
+) {
+    let estimate = crate::transform_to_pose(cybot.single());
+
+    egui::Window::new("Status")
+        .anchor(egui::Align2::RIGHT_TOP, [-8., 8.])
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let now = time.elapsed_seconds_f64();
+            egui::Grid::new("link").show(ui, |ui| {
+                ui.label("Link");
+                match link.pong {
+                    _ if link.up(now) => ui.colored_label(egui::Color32::GREEN, "up"),
+                    Some(pong) => ui.colored_label(
+                        egui::Color32::RED,
+                        format!("no answer for {:.1}s", now - pong),
+                    ),
+                    None => ui.colored_label(egui::Color32::RED, "no answer"),
+                };
+                ui.end_row();
+                ui.label("Latency");
+                match link.rtt {
+                    Some(rtt) => ui.label(format!("{:.0}ms", rtt * 1000.)),
+                    None => ui.label("-"),
+                };
+                ui.end_row();
+            });
+            ui.separator();
+
+            egui::Grid::new("pose").show(ui, |ui| {
+                ui.label("GUI pose");
+                ui.label(format_pose(estimate));
+                ui.end_row();
+                let Some(reported) = status.pose else {
+                    ui.label("Robot pose");
+                    ui.label("-");
+                    ui.end_row();
+                    return;
+                };
+                ui.label("Robot pose");
+                ui.label(format_pose(reported));
+                ui.end_row();
+                // the shortest way around between the two headings
+                let distance = (estimate.x - reported.x).hypot(estimate.y - reported.y);
+                let heading = (estimate.heading - reported.heading + 180.).rem_euclid(360.) - 180.;
+                ui.label("Disagreement");
+                ui.label(format!("{distance:.1}cm {heading:.1}°"));
+                ui.end_row();
+            });
+            ui.separator();
+
+            let Some(telemetry) = status.telemetry else {
+                ui.label("No telemetry received");
+                return;
+            };
+            let age = now - status.received;
+            let [front_left, left, right, front_right] = telemetry.cliff;
+
+            egui::Grid::new("telemetry").show(ui, |ui| {
+                ui.label("Updated");
+                ui.label(format!("{age:.1}s ago"));
+                ui.end_row();
+                ui.label("Battery");
+                ui.label(format!("{:.2}V", telemetry.battery_voltage));
+                ui.end_row();
+                ui.label("Encoders");
+                ui.label(format!(
+                    "L {} R {}",
+                    telemetry.left_encoder, telemetry.right_encoder
+                ));
+                ui.end_row();
+                ui.label("Cliff");
+                ui.label(format!("{front_left} {left} {right} {front_right}"));
+                ui.end_row();
+            });
+        });
+}
+
+fn level_color(level: LogLevel) -> egui::Color32 {
+    match level {
+        LogLevel::Error => egui::Color32::RED,
+        LogLevel::Warn => egui::Color32::YELLOW,
+        LogLevel::Info => egui::Color32::LIGHT_GRAY,
+        LogLevel::Debug => egui::Color32::DARK_GRAY,
+    }
+}
+
+/// Show the firmware's log messages coloured by level in the bottom right of the window
+fn log_panel(mut contexts: EguiContexts, robot: Res<RobotInfo>, log: Res<RobotLog>) {
+    if !robot.capabilities.contains(Capabilities::LOG) {
+        return;
+    }
+
+    egui::Window::new("Robot log")
+        .anchor(egui::Align2::RIGHT_BOTTOM, [-8., -8.])
+        .default_size([300., 150.])
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for (level, message) in &log.messages {
+                        ui.colored_label(level_color(*level), message);
+                    }
+                });
+        });
+}
+
+/// The plugin for showing the state of the robot in the GUI
+pub struct StatusPlugin;
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RobotStatus>()
+            .init_resource::<LinkStatus>()
+            .init_resource::<RobotLog>()
+            .add_system(heartbeat)
+            .add_system(status_panel)
+            .add_system(log_panel);
+    }
+}
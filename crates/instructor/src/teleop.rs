@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use bevy_console::ConsoleOpen;
+use cyproto_core::{Capabilities, Command, VELOCITY_TIMEOUT_MS};
+
+use crate::{Cybot, PathEvent, PreviousCybot, RobotInfo, Socket, State};
+
+/// How fast the robot drives while the up or down arrow is held in cm/s
+const LINEAR_SPEED: f32 = 20.;
+/// How fast the robot turns while the left or right arrow is held in degrees/s
+const ANGULAR_SPEED: f32 = 45.;
+/// How often the velocity is resent to keep the robot's deadman from running out in seconds
+const HEARTBEAT_PERIOD: f64 = VELOCITY_TIMEOUT_MS as f64 / 1000. / 4.;
+
+/// The velocity the robot was last told to follow
+#[derive(Debug, Default, Resource)]
+pub struct Teleop {
+    pub linear: f32,
+    pub angular: f32,
+    /// When the velocity was last sent in seconds since startup
+    pub sent: f64,
+}
+
+impl Teleop {
+    pub fn moving(&self) -> bool {
+        self.linear != 0. || self.angular != 0.
+    }
+}
+
+/// Drive the robot live with the arrow keys
+///
+/// The velocity is sent whenever it changes and resent periodically while the robot is moving,
+/// if the keys are released or the GUI stops sending the robot stops on its own.
+#[allow(clippy::too_many_arguments)]
+fn teleop_keys(
+    keys: Res<Input<KeyCode>>,
+    console_open: Res<ConsoleOpen>,
+    robot: Res<RobotInfo>,
+    state: Res<State>,
+    time: Res<Time>,
+    mut socket: ResMut<Socket>,
+    mut teleop: ResMut<Teleop>,
+    mut ev_path: EventWriter<PathEvent>,
+    mut cybot: Query<&mut Transform, (With<Cybot>, Without<PreviousCybot>)>,
+    mut prev: Query<&mut Transform, (With<PreviousCybot>, Without<Cybot>)>,
+) {
+    if !robot.capabilities.contains(Capabilities::TELEOP) {
+        return;
+    }
+
+    // the arrow keys belong to the console while it is open
+    let (linear, angular) = if console_open.open || !matches!(*state, State::Normal) {
+        (0., 0.)
+    } else {
+        let axis = |pos, neg| keys.pressed(pos) as i8 as f32 - keys.pressed(neg) as i8 as f32;
+        (
+            axis(KeyCode::Up, KeyCode::Down) * LINEAR_SPEED,
+            axis(KeyCode::Left, KeyCode::Right) * ANGULAR_SPEED,
+        )
+    };
+
+    let now = time.elapsed_seconds_f64();
+    let changed = linear != teleop.linear || angular != teleop.angular;
+    if !changed && (!teleop.moving() || now - teleop.sent < HEARTBEAT_PERIOD) {
+        return;
+    }
+
+    // the robot has been following the old velocity since it was sent
+    if teleop.moving() {
+        let elapsed = (now - teleop.sent) as f32;
+        crate::arc_by(
+            teleop.linear * elapsed,
+            teleop.angular * elapsed,
+            &mut cybot.single_mut(),
+            &mut prev.single_mut(),
+            &mut ev_path,
+        );
+    }
+
+    // a failed send shows up as the link going down, and the robot's deadman stops it on its own
+    if crate::com::send_command(&mut socket, Command::SetVelocity { linear, angular }).is_err() {
+        *teleop = Teleop::default();
+        return;
+    }
+    *teleop = Teleop {
+        linear,
+        angular,
+        sent: now,
+    };
+}
+
+/// The plugin for driving the robot live from the GUI
+pub struct TeleopPlugin;
+
+impl Plugin for TeleopPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Teleop>().add_system(teleop_keys);
+    }
+}
@@ -1,82 +1,698 @@
-use std::{net::{TcpListener, TcpStream}, io::{self, BufRead, Write}, time::Duration};
+use std::{collections::HashMap, ffi::{c_char, c_void, CStr, CString}, net::{TcpListener, TcpStream}, io::{self, Write}, sync::{Arc, Mutex}, time::{Duration, Instant}};
 
-use cyproto_core::{Response, Command, ObjectData};
-use rand::Rng;
+use cyproto_core::{transport::{Link, LinkError, StdIo}, Command, Frame, ScanSensor, Seq, Step};
+use rand::{rngs::ThreadRng, Rng};
 
-pub fn read_command(stream: &mut TcpStream) -> Result<Command, Box<dyn std::error::Error>> {
-    let mut reader = io::BufReader::with_capacity(1, stream);
+/// The writing half of the connection, shared with the telemetry thread
+type Writer = Arc<Mutex<TcpStream>>;
 
-    let mut buffer = Vec::new();
-    reader.read_until(0, &mut buffer)?;
+/// The calibration values of the robot, kept across connections until the mock restarts
+type Config = HashMap<&'static str, f32>;
 
-    let command: Command = postcard::from_bytes_cobs(&mut buffer)?;
-    Ok(command)
+/// The calibration values the mock robot starts with
+const CONFIG_DEFAULTS: [(&str, f32); 5] = [
+    ("wheel_factor", 1.),
+    ("turn_factor", 1.),
+    ("ir_scale", 86000.),
+    ("ir_exponent", -1.15),
+    ("servo_offset", 0.),
+];
+
+extern "C" fn get_config(user: *mut c_void, key: *const c_char, value: *mut f32) -> bool {
+    let config = unsafe { &*(user as *const Config) };
+    let key = unsafe { CStr::from_ptr(key) }.to_str().unwrap_or_default();
+    match config.get(key) {
+        Some(val) => {
+            unsafe { *value = *val };
+            true
+        }
+        None => false,
+    }
+}
+
+extern "C" fn set_config(user: *mut c_void, key: *const c_char, value: f32) -> bool {
+    let config = unsafe { &mut *(user as *mut Config) };
+    let key = unsafe { CStr::from_ptr(key) }.to_str().unwrap_or_default();
+    match config.get_mut(key) {
+        Some(val) if value.is_finite() => {
+            *val = value;
+            true
+        }
+        _ => false,
+    }
+}
+
+const TICKS_PER_CM: f32 = 20.;
+const WHEEL_BASE_CM: f32 = 23.;
+const TELEMETRY_PERIOD: Duration = Duration::from_millis(500);
+const PROGRESS_PERIOD: Duration = Duration::from_millis(200);
+/// The environment variable setting how often a command gets lost on its way to the robot like
+/// it would on flaky WiFi, from 0 to 1, no command is lost when it is not set
+const LOSS_VAR: &str = "CYPROTO_MOCK_LOSS";
+/// The environment variable that makes the mock print what it logs and which commands it loses
+const VERBOSE_VAR: &str = "CYPROTO_MOCK_VERBOSE";
+
+/// Whether the mock should describe what it is doing on stdout
+fn verbose() -> bool {
+    std::env::var_os(VERBOSE_VAR).is_some()
+}
+
+/// The cliff sensors that can see the tape at the edge of the field
+const BOUNDARY_SENSORS: [cyproto_executor::CliffSensor; 4] = {
+    use cyproto_executor::CliffSensor::*;
+    [FrontLeft, Left, Right, FrontRight]
+};
+
+/// The ways a simulated drive can end early
+const FAILURES: [cyproto_executor::DriveTermination; 12] = {
+    use cyproto_executor::DriveTermination::*;
+    [
+        BumpLeft,
+        BumpRight,
+        BumpBoth,
+        CliffFrontLeft,
+        CliffLeft,
+        CliffRight,
+        CliffFrontRight,
+        BoundaryFrontLeft,
+        BoundaryLeft,
+        BoundaryRight,
+        BoundaryFrontRight,
+        Timeout,
+    ]
+};
+
+/// The simulated state of the robot's wheels
+#[derive(Default)]
+pub struct Odometry {
+    pose: cyproto_executor::Pose,
+    left: i32,
+    right: i32,
+    battery: f32,
+}
+
+impl Odometry {
+    pub fn new() -> Self {
+        Self { battery: 14.4, ..Default::default() }
+    }
+
+    pub fn drive(&mut self, distance: f32) {
+        let heading = self.pose.heading.to_radians();
+        self.pose.x -= distance * heading.sin();
+        self.pose.y += distance * heading.cos();
+        self.left += (distance * TICKS_PER_CM) as i32;
+        self.right += (distance * TICKS_PER_CM) as i32;
+        self.battery -= distance.abs() * 0.001;
+    }
+
+    pub fn turn(&mut self, angle: f32) {
+        let arc = angle.to_radians() * WHEEL_BASE_CM / 2.;
+        self.pose.heading = (self.pose.heading + angle).rem_euclid(360.);
+        self.left -= (arc * TICKS_PER_CM) as i32;
+        self.right += (arc * TICKS_PER_CM) as i32;
+        self.battery -= arc.abs() * 0.001;
+    }
+
+    /// Drive `distance` along a circle while turning by `angle` degrees
+    pub fn arc(&mut self, distance: f32, angle: f32) {
+        let turned = angle.to_radians();
+        // the straight line between the start and end of the arc points halfway through the turn
+        let chord = if turned == 0. {
+            distance
+        } else {
+            2. * (distance / turned) * (turned / 2.).sin()
+        };
+        let heading = self.pose.heading.to_radians() + turned / 2.;
+        self.pose.x -= chord * heading.sin();
+        self.pose.y += chord * heading.cos();
+        self.pose.heading = (self.pose.heading + angle).rem_euclid(360.);
+
+        let arc = turned * WHEEL_BASE_CM / 2.;
+        self.left += ((distance - arc) * TICKS_PER_CM) as i32;
+        self.right += ((distance + arc) * TICKS_PER_CM) as i32;
+        self.battery -= distance.abs() * 0.001;
+    }
+
+    pub fn sensors(&self, rand: &mut impl Rng) -> cyproto_executor::Sensors {
+        let distance: f32 = rand.gen_range(20.0..150.);
+        cyproto_executor::Sensors {
+            bump_left: rand.gen_bool(0.05),
+            bump_right: rand.gen_bool(0.05),
+            cliff: [(); 4].map(|_| rand.gen_range(1200..1500)),
+            servo_angle: 90,
+            ir_distance: (distance + rand.gen_range(-2.0..2.)).min(80.),
+            ping_distance: distance + rand.gen_range(-1.0..1.),
+            battery_voltage: self.battery + rand.gen_range(-0.05..0.05),
+        }
+    }
+
+    pub fn telemetry(&self, rand: &mut impl Rng) -> cyproto_executor::Telemetry {
+        cyproto_executor::Telemetry {
+            battery_voltage: self.battery + rand.gen_range(-0.05..0.05),
+            left_encoder: self.left,
+            right_encoder: self.right,
+            cliff: [(); 4].map(|_| rand.gen_range(1200..1500)),
+            pose: self.pose,
+        }
+    }
+}
+
+/// A velocity the robot keeps following until it is replaced or runs out
+pub struct Teleop {
+    linear: f32,
+    angular: f32,
+    /// When the velocity arrived in milliseconds since the connection was opened
+    received: u32,
+    moved: Instant,
+}
+
+impl Teleop {
+    /// Move the robot for the time since it last moved and check whether the deadman ran out
+    ///
+    /// Returns false once the robot stopped because no velocity arrived in time.
+    pub fn follow(&mut self, odometry: &Mutex<Odometry>, now_ms: u32) -> bool {
+        let elapsed = self.moved.elapsed().as_secs_f32();
+        self.moved = Instant::now();
+        odometry.lock().unwrap().arc(self.linear * elapsed, self.angular * elapsed);
+
+        !cyproto_executor::cyproto_deadman_expired(self.received, now_ms)
+    }
 }
 
-pub fn send_response(stream: &mut TcpStream, res: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-    stream.write_all(res)?;
+/// The reading half of the connection
+type Reader = Link<StdIo<TcpStream>>;
+
+/// Whether reading failed only because no command arrived before the read timeout
+fn timed_out(err: &LinkError<io::Error>) -> bool {
+    matches!(err, LinkError::Transport(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut))
+}
+
+pub fn send_response(stream: &Writer, res: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    stream.lock().unwrap().write_all(res)?;
     Ok(())
 }
 
-fn main() {
+/// Tell the host what the robot is up to
+pub fn send_log(stream: &Writer, level: cyproto_executor::LogLevel, message: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if verbose() {
+        println!("{level:?}: {message}");
+    }
+    let message = CString::new(message)?;
+    let mut buf = [0; cyproto_core::BYTES_MAX];
+    let len = cyproto_executor::cyproto_log(level, message.as_ptr(), buf.as_mut_ptr());
+    send_response(stream, &buf[..len])
+}
+
+/// Periodically send telemetry until the connection is closed
+pub fn send_telemetry(stream: Writer, odometry: Arc<Mutex<Odometry>>) {
     let mut rand = rand::thread_rng();
+    loop {
+        std::thread::sleep(TELEMETRY_PERIOD);
+        let telemetry = odometry.lock().unwrap().telemetry(&mut rand);
+
+        let mut buf = [0; cyproto_core::BYTES_MAX];
+        let len = cyproto_executor::cyproto_telemetry(telemetry, buf.as_mut_ptr());
+        if send_response(&stream, &buf[..len]).is_err() {
+            break;
+        }
+    }
+}
+
+/// Pretend to run a command for the given duration while watching for a stop command
+///
+/// `on_progress` is periodically called with the fraction of the command that has completed.
+/// Returns the sequence number of the stop and the fraction of the command that completed
+/// if the command was interrupted.
+pub fn wait_for_stop(
+    reader: &mut Reader,
+    writer: &Writer,
+    duration: Duration,
+    mut on_progress: impl FnMut(f32) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<Option<(Seq, f32)>, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let mut stopped = None;
+    while stopped.is_none() {
+        let elapsed = started.elapsed();
+        if elapsed >= duration {
+            break;
+        }
+        reader.transport().0.set_read_timeout(Some((duration - elapsed).min(PROGRESS_PERIOD)))?;
+
+        match reader.recv_command() {
+            Ok(Frame { seq, msg: Command::Stop }) => {
+                let mut buf = [0; cyproto_core::BYTES_MAX];
+                let len = cyproto_executor::cyproto_ack(seq, buf.as_mut_ptr());
+                send_response(writer, &buf[..len])?;
+                stopped = Some((seq, started.elapsed().min(duration).as_secs_f32() / duration.as_secs_f32()));
+            }
+            Ok(Frame { seq, msg: Command::Ping { nonce } }) => {
+                let mut buf = [0; cyproto_core::BYTES_MAX];
+                let len = cyproto_executor::cyproto_pong(seq, nonce, buf.as_mut_ptr());
+                send_response(writer, &buf[..len])?;
+            }
+            Ok(Frame { seq, msg }) if msg.acknowledged() => {
+                let mut buf = [0; cyproto_core::BYTES_MAX];
+                let len = cyproto_executor::cyproto_nack(seq, cyproto_executor::ErrorCode::Busy, buf.as_mut_ptr());
+                send_response(writer, &buf[..len])?;
+            }
+            // nothing on the host waits on an answer to a command without an acknowledgement
+            Ok(_) => {}
+            // the host finds out the command was lost when it is not acknowledged
+            Err(LinkError::Codec(err)) => {
+                send_log(writer, cyproto_executor::LogLevel::Warn, &format!("Dropped a command: {err}"))?;
+            }
+            Err(err) if timed_out(&err) => {
+                let elapsed = started.elapsed();
+                if elapsed < duration {
+                    on_progress(elapsed.as_secs_f32() / duration.as_secs_f32())?;
+                }
+            }
+            Err(err) => return Err(Box::new(err)),
+        }
+    }
+    reader.transport().0.set_read_timeout(None)?;
+    Ok(stopped)
+}
+
+/// How a command that takes a while ended
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Completed,
+    /// The command finished without getting as far as it was asked to
+    Failed,
+    /// The command was answered with an error
+    Rejected,
+    /// The command was interrupted by a stop which was answered with its result
+    Stopped,
+}
+
+/// The simulated robot on the other end of a connection
+pub struct Robot {
+    reader: Reader,
+    writer: Writer,
+    odometry: Arc<Mutex<Odometry>>,
+    config: Config,
+    rand: ThreadRng,
+}
+
+impl Robot {
+    /// Send the response written into the buffer by one of the executor functions
+    pub fn respond(&self, write: impl FnOnce(*mut u8) -> usize) {
+        let mut buf = [0; cyproto_core::BYTES_MAX];
+        let len = write(buf.as_mut_ptr());
+        send_response(&self.writer, &buf[..len]).unwrap();
+    }
+
+    /// Reject a command because one of its arguments is out of range
+    pub fn reject(&self, seq: Seq, detail: &CStr) -> Outcome {
+        self.respond(|buf| {
+            cyproto_executor::cyproto_error(seq, cyproto_executor::ErrorCode::InvalidArgument, detail.as_ptr(), buf)
+        });
+        Outcome::Rejected
+    }
+
+    pub fn get_config(&self, seq: Seq, key: &str) {
+        let cmd = cyproto_executor::ConfigCommand::new(key, 0.);
+        let user = &self.config as *const Config as *mut c_void;
+        self.respond(|buf| cyproto_executor::cyproto_get_config(seq, cmd, get_config, user, buf));
+    }
+
+    pub fn set_config(&mut self, seq: Seq, key: &str, value: f32) {
+        let cmd = cyproto_executor::ConfigCommand::new(key, value);
+        // the callbacks change the config so it must not be borrowed by respond at the same time
+        let mut buf = [0; cyproto_core::BYTES_MAX];
+        let user = &mut self.config as *mut Config as *mut c_void;
+        let old = self.config.get(key).copied();
+        let len = cyproto_executor::cyproto_set_config(seq, cmd, set_config, get_config, user, buf.as_mut_ptr());
+        send_response(&self.writer, &buf[..len]).unwrap();
+        match (old, self.config.get(key).copied()) {
+            (Some(old), Some(new)) if old != new => {
+                self.log(cyproto_executor::LogLevel::Info, &format!("{key} changed from {old} to {new}"));
+            }
+            _ => {}
+        }
+    }
+
+    /// Tell the host what the robot is up to
+    pub fn log(&self, level: cyproto_executor::LogLevel, message: &str) {
+        send_log(&self.writer, level, message).unwrap();
+    }
+
+    /// Decide up front how far a motion gets so the progress heads towards it
+    fn motion_total(&mut self, target: f32) -> (f32, bool) {
+        let failed = self.rand.gen_bool(0.1);
+        let range = if target < 0. {
+            target..=0.0
+        } else {
+            0.0..=target
+        };
+        let total = if failed { self.rand.gen_range(range) } else { target };
+        (total, failed)
+    }
+
+    pub fn drive(&mut self, seq: Seq, distance: f32) -> Outcome {
+        let (total_distance, failed) = self.motion_total(distance);
+
+        let Robot { reader, writer, .. } = self;
+        let stopped = wait_for_stop(reader, writer, Duration::from_secs(1), |done| {
+            let mut buf = [0; cyproto_core::BYTES_MAX];
+            let len = cyproto_executor::cyproto_drive_progress(seq, total_distance * done, buf.as_mut_ptr());
+            send_response(writer, &buf[..len])
+        }).unwrap();
+
+        let (seq, total_distance, termination) = match stopped {
+            Some((stop_seq, done)) => (
+                stop_seq,
+                total_distance * done,
+                cyproto_executor::DriveTermination::StoppedByHost,
+            ),
+            None if failed => (seq, total_distance, FAILURES[self.rand.gen_range(0..FAILURES.len())]),
+            None => (seq, total_distance, cyproto_executor::DriveTermination::Completed),
+        };
+        let mut odometry = self.odometry.lock().unwrap();
+        odometry.drive(total_distance);
+        let done = cyproto_executor::DriveDone {
+            total_distance,
+            termination,
+            has_pose: true,
+            pose: odometry.pose,
+        };
+        drop(odometry);
+
+        self.respond(|buf| cyproto_executor::cyproto_drive_done(seq, done, buf));
+        match termination {
+            cyproto_executor::DriveTermination::Completed => Outcome::Completed,
+            cyproto_executor::DriveTermination::StoppedByHost => Outcome::Stopped,
+            _ => Outcome::Failed,
+        }
+    }
+
+    pub fn turn(&mut self, seq: Seq, angle: f32) -> Outcome {
+        let (total_angle, failed) = self.motion_total(angle);
+
+        let Robot { reader, writer, .. } = self;
+        let stopped = wait_for_stop(reader, writer, Duration::from_secs(1), |done| {
+            let mut buf = [0; cyproto_core::BYTES_MAX];
+            let len = cyproto_executor::cyproto_turn_progress(seq, total_angle * done, buf.as_mut_ptr());
+            send_response(writer, &buf[..len])
+        }).unwrap();
+
+        let (seq, total_angle) = match stopped {
+            Some((stop_seq, done)) => (stop_seq, total_angle * done),
+            None => (seq, total_angle),
+        };
+        let mut odometry = self.odometry.lock().unwrap();
+        odometry.turn(total_angle);
+        let done = cyproto_executor::TurnDone {
+            total_angle,
+            has_pose: true,
+            pose: odometry.pose,
+        };
+        drop(odometry);
+
+        self.respond(|buf| cyproto_executor::cyproto_turn_done(seq, done, buf));
+        match (stopped, failed) {
+            (Some(_), _) => Outcome::Stopped,
+            (None, true) => Outcome::Failed,
+            (None, false) => Outcome::Completed,
+        }
+    }
+
+    pub fn arc(&mut self, seq: Seq, radius: f32, distance: f32) -> Outcome {
+        if radius == 0. {
+            return self.reject(seq, c"use turn to spin in place");
+        }
+        let (total_distance, failed) = self.motion_total(distance);
+
+        let stopped = wait_for_stop(&mut self.reader, &self.writer, Duration::from_secs(1), |_| Ok(())).unwrap();
+        let (seq, total_distance) = match stopped {
+            Some((stop_seq, done)) => (stop_seq, total_distance * done),
+            None => (seq, total_distance),
+        };
+        let total_angle = (total_distance / radius).to_degrees();
+        let mut odometry = self.odometry.lock().unwrap();
+        odometry.arc(total_distance, total_angle);
+        let done = cyproto_executor::ArcDone {
+            total_distance,
+            total_angle,
+            has_pose: true,
+            pose: odometry.pose,
+        };
+        drop(odometry);
+
+        self.respond(|buf| cyproto_executor::cyproto_arc_done(seq, done, buf));
+        match (stopped, failed) {
+            (Some(_), _) => Outcome::Stopped,
+            (None, true) => Outcome::Failed,
+            (None, false) => Outcome::Completed,
+        }
+    }
+
+    pub fn scan(&mut self, seq: Seq, start: u8, end: u8, step: Option<u8>, sensor: ScanSensor) -> Outcome {
+        if start > end || step == Some(0) {
+            return self.reject(seq, c"scan has no angles");
+        }
+
+        let stopped = wait_for_stop(&mut self.reader, &self.writer, Duration::from_secs(1), |_| Ok(())).unwrap();
+        // an interrupted scan only reports the objects it swept past
+        let (seq, end) = match stopped {
+            Some((stop_seq, done)) => (stop_seq, start + ((end - start) as f32 * done) as u8),
+            None => (seq, end),
+        };
+        let outcome = if stopped.is_some() { Outcome::Stopped } else { Outcome::Completed };
+        // objects can only be found at the angles the servo stopped at
+        let step = step.unwrap_or(1);
+        let max_distance = match sensor {
+            ScanSensor::Ir => 80.,
+            ScanSensor::Ping | ScanSensor::Both => 200.,
+        };
+        let num_objs: usize = self.rand.gen_range(0..=40);
+        let objs: Vec<_> = (0..num_objs)
+            .map(|_| cyproto_executor::ObjectData {
+                distance: self.rand.gen_range(15.0..max_distance),
+                width: self.rand.gen_range(5.0..10.),
+                angle: start + self.rand.gen_range(0..=(end - start) / step) * step,
+            })
+            .collect();
+
+        if objs.len() <= cyproto_executor::max_objects() {
+            self.respond(|buf| cyproto_executor::cyproto_scan_done(seq, cyproto_executor::ScanDone {
+                objects: objs.as_ptr(),
+                size: objs.len(),
+            }, buf));
+            return outcome;
+        }
+
+        // too many objects for a single response so stream them in chunks
+        let total = cyproto_executor::cyproto_scan_chunks(objs.len()) as u16;
+        for (index, chunk) in objs.chunks(cyproto_executor::max_objects()).enumerate() {
+            self.respond(|buf| cyproto_executor::cyproto_scan_chunk(seq, cyproto_executor::ScanChunk {
+                index: index as u16,
+                total,
+                size: chunk.len(),
+                objects: chunk.as_ptr(),
+            }, buf));
+        }
+        self.respond(|buf| cyproto_executor::cyproto_scan_end(seq, objs.len() as u16, buf));
+        outcome
+    }
+
+    pub fn sweep(&mut self, seq: Seq, start: u8, end: u8, step: u8) -> Outcome {
+        let cmd = cyproto_executor::SweepCommand { start, end, step };
+        let size = cyproto_executor::cyproto_sweep_size(cmd);
+        if size == 0 {
+            return self.reject(seq, c"sweep has no readings");
+        }
+
+        let stopped = wait_for_stop(&mut self.reader, &self.writer, Duration::from_secs(1), |_| Ok(())).unwrap();
+        let (seq, size) = match stopped {
+            Some((stop_seq, done)) => (stop_seq, (size as f32 * done) as usize),
+            None => (seq, size),
+        };
+        // walk the distance around so neighbouring readings look like surfaces
+        let mut distance: f32 = self.rand.gen_range(20.0..150.);
+        let readings: Vec<_> = (0..size)
+            .map(|i| {
+                distance = (distance + self.rand.gen_range(-8.0..8.)).clamp(10., 250.);
+                cyproto_executor::SweepData {
+                    angle: start + i as u8 * step,
+                    ir_distance: (distance + self.rand.gen_range(-2.0..2.)).min(80.),
+                    ping_distance: distance + self.rand.gen_range(-1.0..1.),
+                }
+            })
+            .collect();
+
+        let total = cyproto_executor::cyproto_sweep_chunks(readings.len()) as u16;
+        for (index, chunk) in readings.chunks(cyproto_executor::cyproto_max_readings()).enumerate() {
+            self.respond(|buf| cyproto_executor::cyproto_sweep_chunk(seq, cyproto_executor::SweepChunk {
+                index: index as u16,
+                total,
+                size: chunk.len(),
+                readings: chunk.as_ptr(),
+            }, buf));
+        }
+        self.respond(|buf| cyproto_executor::cyproto_sweep_end(seq, readings.len() as u16, buf));
+        if stopped.is_some() { Outcome::Stopped } else { Outcome::Completed }
+    }
+
+    /// Run the steps of a sequence until one of them does not complete
+    pub fn sequence(&mut self, seq: Seq, steps: &[Step]) {
+        let mut completed = 0;
+        for step in steps {
+            let outcome = match *step {
+                Step::Drive { distance, .. } => self.drive(seq, distance),
+                Step::Turn { angle, .. } => self.turn(seq, angle),
+                Step::Arc { radius, distance, .. } => self.arc(seq, radius, distance),
+                Step::Scan { start, end, step, sensor } => self.scan(seq, start, end, step, sensor),
+            };
+            match outcome {
+                Outcome::Completed => completed += 1,
+                Outcome::Failed => break,
+                // the error or the stop already ended the sequence
+                Outcome::Rejected | Outcome::Stopped => return,
+            }
+        }
+        self.respond(|buf| cyproto_executor::cyproto_sequence_done(seq, completed, buf));
+    }
+}
+
+fn main() {
     let listener = TcpListener::bind("localhost:2888").unwrap();
+    let mut config: Config = CONFIG_DEFAULTS.into_iter().collect();
+    let loss = match std::env::var(LOSS_VAR) {
+        Ok(val) => val
+            .parse::<f64>()
+            .ok()
+            .filter(|loss| (0. ..=1.).contains(loss))
+            .unwrap_or_else(|| panic!("{LOSS_VAR} must be a number from 0 to 1")),
+        Err(_) => 0.,
+    };
 
     loop {
-        let (mut stream, _) = listener.accept().unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let writer = Arc::new(Mutex::new(stream.try_clone().unwrap()));
+        let odometry = Arc::new(Mutex::new(Odometry::new()));
+        {
+            let writer = writer.clone();
+            let odometry = odometry.clone();
+            std::thread::spawn(move || send_telemetry(writer, odometry));
+        }
+        let mut robot = Robot {
+            reader: Link::new(StdIo(stream)),
+            writer,
+            odometry,
+            config,
+            rand: rand::thread_rng(),
+        };
+
+        let opened = Instant::now();
+        let now_ms = || opened.elapsed().as_millis() as u32;
+        let mut teleop: Option<Teleop> = None;
+
+        loop {
+            // wake up regularly while moving on our own to keep the deadman in check
+            robot.reader.transport().0.set_read_timeout(teleop.as_ref().map(|_| PROGRESS_PERIOD)).unwrap();
+            let res = robot.reader.recv_command();
+            if let Some(velocity) = &mut teleop {
+                if !velocity.follow(&robot.odometry, now_ms()) {
+                    robot.log(cyproto_executor::LogLevel::Warn, "No velocity arrived in time, stopping");
+                    teleop = None;
+                }
+                // every now and then drive over the tape at the edge of the field
+                if robot.rand.gen_bool(0.05) {
+                    let sensor = BOUNDARY_SENSORS[robot.rand.gen_range(0..BOUNDARY_SENSORS.len())];
+                    let signal = robot.rand.gen_range(2600..2900);
+                    robot.respond(|buf| cyproto_executor::cyproto_boundary(sensor, signal, buf));
+                }
+            }
+            let Frame { seq, msg: cmd } = match res {
+                Ok(frame) => frame,
+                Err(LinkError::Codec(err)) => {
+                    robot.log(cyproto_executor::LogLevel::Warn, &format!("Dropped a command: {err}"));
+                    continue;
+                }
+                Err(err) if timed_out(&err) => continue,
+                Err(_) => break,
+            };
+            if cmd.acknowledged() {
+                if robot.rand.gen_bool(loss) {
+                    if verbose() {
+                        println!("Losing command {seq}: {cmd:?}");
+                    }
+                    continue;
+                }
+                robot.respond(|buf| cyproto_executor::cyproto_ack(seq, buf));
+            }
+            // any other command takes over from the velocity
+            if !matches!(cmd, Command::Hello { .. } | Command::SetVelocity { .. } | Command::Ping { .. }) {
+                teleop = None;
+            }
 
-        while let Ok(cmd) = read_command(&mut stream) {
-            std::thread::sleep(Duration::from_secs(1));
             match cmd {
+                Command::Hello { .. } => {
+                    let caps = cyproto_executor::CYPROTO_CAP_SCAN
+                        | cyproto_executor::CYPROTO_CAP_STOP
+                        | cyproto_executor::CYPROTO_CAP_SWEEP
+                        | cyproto_executor::CYPROTO_CAP_TELEMETRY
+                        | cyproto_executor::CYPROTO_CAP_ARC
+                        | cyproto_executor::CYPROTO_CAP_TELEOP
+                        | cyproto_executor::CYPROTO_CAP_PING
+                        | cyproto_executor::CYPROTO_CAP_SENSORS
+                        | cyproto_executor::CYPROTO_CAP_BOUNDARY
+                        | cyproto_executor::CYPROTO_CAP_SEQUENCE
+                        | cyproto_executor::CYPROTO_CAP_CONFIG
+                        | cyproto_executor::CYPROTO_CAP_LOG
+                        | cyproto_executor::CYPROTO_CAP_ACK;
+                    robot.respond(|buf| cyproto_executor::cyproto_hello(seq, caps, buf));
+                    robot.log(cyproto_executor::LogLevel::Info, "Mock robot ready");
+                }
+                Command::Stop => {
+                    robot.respond(|buf| cyproto_executor::cyproto_stopped(seq, buf));
+                }
                 Command::Drive { distance, .. } => {
-                    let failed = rand.gen_bool(0.1);
-                    let range = if distance < 0. {
-                        distance..=0.0
-                    } else {
-                        0.0..=distance
-                    };
-
-                    let mut buf = [0; cyproto_core::BYTES_MAX];
-                    let len = cyproto_executor::cyproto_drive_done(cyproto_executor::DriveDone {
-                        total_distance: if failed { rand.gen_range(range) } else { distance },
-                        bump_detected: if failed { rand.gen_bool(0.5) } else { false },
-                        cliff_detected: if failed { rand.gen_bool(0.5) } else { false },
-                    }, buf.as_mut_ptr());
-                    send_response(&mut stream, &buf[..len]).unwrap();
+                    robot.drive(seq, distance);
                 }
                 Command::Turn { angle, .. } => {
-                    let failed = rand.gen_bool(0.1);
-                    let range = if angle < 0. {
-                        angle..=0.
-                    } else {
-                        0.0..=angle
-                    };
-
-                    let mut buf = [0; cyproto_core::BYTES_MAX];
-                    let len = cyproto_executor::cyproto_turn_done(cyproto_executor::TurnDone {
-                        total_angle: if failed { rand.gen_range(range) } else { angle },
-                    }, buf.as_mut_ptr());
-                    send_response(&mut stream, &buf[..len]).unwrap();
-                }
-                Command::Scan { start, end } => {
-                    let num_objs: usize = rand.gen_range(0..=10);
-                    let mut objs: heapless::Vec<cyproto_executor::ObjectData, {cyproto_core::SCAN_MAX}> = heapless::Vec::new();
-                    for _ in 0..num_objs {
-                        objs.push(cyproto_executor::ObjectData {
-                            distance: rand.gen_range(15.0..80.),
-                            width: rand.gen_range(5.0..10.),
-                            angle: rand.gen_range(start..=end)
-                        }).unwrap();
-                    }
-
-                    let mut buf = [0; cyproto_core::BYTES_MAX];
-                    let len = cyproto_executor::cyproto_scan_done(cyproto_executor::ScanDone {
-                        objects: objs.as_ptr(),
-                        size: objs.len(),
-                    }, buf.as_mut_ptr());
-                    send_response(&mut stream, &buf[..len]).unwrap();
-                } 
+                    robot.turn(seq, angle);
+                }
+                Command::Arc { radius, distance, .. } => {
+                    robot.arc(seq, radius, distance);
+                }
+                Command::Ping { nonce } => {
+                    robot.respond(|buf| cyproto_executor::cyproto_pong(seq, nonce, buf));
+                }
+                Command::ReadSensors => {
+                    let sensors = robot.odometry.lock().unwrap().sensors(&mut robot.rand);
+                    robot.respond(|buf| cyproto_executor::cyproto_sensors(seq, sensors, buf));
+                }
+                Command::SetVelocity { linear: 0., angular: 0. } => {
+                    teleop = None;
+                }
+                Command::SetVelocity { linear, angular } => {
+                    teleop = Some(Teleop {
+                        linear,
+                        angular,
+                        received: now_ms(),
+                        moved: Instant::now(),
+                    });
+                }
+                Command::Scan { start, end, step, sensor } => {
+                    robot.scan(seq, start, end, step, sensor);
+                }
+                Command::Sweep { start, end, step } => {
+                    robot.sweep(seq, start, end, step);
+                }
+                Command::Sequence(steps) => {
+                    robot.sequence(seq, &steps);
+                }
+                Command::GetConfig { key } => {
+                    robot.get_config(seq, &key);
+                }
+                Command::SetConfig { key, value } => {
+                    robot.set_config(seq, &key, value);
+                }
             }
         }
+        config = robot.config;
     }
 }